@@ -0,0 +1,5 @@
+
+
+DROP INDEX IF EXISTS ix_cache_entries_expires_at;
+
+DROP TABLE IF EXISTS cache_entries;
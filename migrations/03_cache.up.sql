@@ -0,0 +1,11 @@
+
+
+-- Shared cache (tickets, sessions) for horizontally scaled instances.
+-- UNLOGGED: records are short-lived, so they are not worth WAL writes and may be lost on crash
+CREATE UNLOGGED TABLE IF NOT EXISTS cache_entries (
+    key TEXT PRIMARY KEY,
+    value JSONB NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS ix_cache_entries_expires_at ON cache_entries (expires_at);
@@ -0,0 +1,13 @@
+
+
+-- Snowflake worker ids (10 bits) leased by running instances.
+-- A lease is free when its heartbeat is older than the lease lifetime
+CREATE TABLE IF NOT EXISTS snowflake_workers (
+    worker_id INTEGER PRIMARY KEY CHECK (worker_id BETWEEN 0 AND 1023),
+    instance TEXT DEFAULT NULL,
+    heartbeat_at TIMESTAMPTZ NOT NULL DEFAULT 'epoch'
+);
+
+INSERT INTO snowflake_workers (worker_id)
+SELECT worker_id FROM generate_series(0, 1023) AS worker_id
+ON CONFLICT DO NOTHING;
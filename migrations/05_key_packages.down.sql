@@ -0,0 +1,9 @@
+
+
+DROP INDEX IF EXISTS ix_key_package_claims_claimed_by;
+
+DROP TABLE IF EXISTS key_package_claims;
+
+DROP INDEX IF EXISTS ix_key_packages_user_device;
+
+DROP TABLE IF EXISTS key_packages;
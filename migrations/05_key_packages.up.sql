@@ -0,0 +1,27 @@
+
+
+-- MLS KeyPackages uploaded by devices, each of them is claimed (deleted) once
+CREATE TABLE IF NOT EXISTS key_packages (
+    id BIGINT PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id BIGINT NOT NULL,
+    ciphersuite INTEGER NOT NULL,
+    key_package_ref BYTEA NOT NULL UNIQUE,
+    data BYTEA NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS ix_key_packages_user_device ON key_packages (user_id, device_id);
+
+-- Claimed KeyPackages, for the claim limits and so a Welcome can be routed to the device which uploaded the KeyPackage
+CREATE TABLE IF NOT EXISTS key_package_claims (
+    key_package_ref BYTEA PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id BIGINT NOT NULL,
+    claimed_by BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    claimed_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Claims of a user within the limit window
+CREATE INDEX IF NOT EXISTS ix_key_package_claims_claimed_by ON key_package_claims (claimed_by, claimed_at);
@@ -0,0 +1,7 @@
+
+
+DROP INDEX IF EXISTS ix_mls_messages_chat;
+
+DROP TABLE IF EXISTS mls_messages;
+
+DROP TABLE IF EXISTS mls_groups;
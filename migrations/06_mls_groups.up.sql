@@ -0,0 +1,23 @@
+
+
+-- MLS groups relayed by the delivery service, every group belongs to a chat
+CREATE TABLE IF NOT EXISTS mls_groups (
+    group_id BYTEA PRIMARY KEY,
+    chat_id BIGINT NOT NULL UNIQUE REFERENCES chats(id) ON DELETE CASCADE,
+    epoch BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Handshake and application messages in the order they were accepted
+CREATE TABLE IF NOT EXISTS mls_messages (
+    id BIGINT PRIMARY KEY,
+    group_id BYTEA NOT NULL REFERENCES mls_groups(group_id) ON DELETE CASCADE,
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    sender_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    epoch BIGINT NOT NULL,
+    content_type SMALLINT NOT NULL,
+    data BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS ix_mls_messages_chat ON mls_messages (chat_id, id);
@@ -0,0 +1,5 @@
+
+
+DROP INDEX IF EXISTS ix_mls_welcomes_user_device;
+
+DROP TABLE IF EXISTS mls_welcomes;
@@ -0,0 +1,15 @@
+
+
+-- Per-device mailbox, a Welcome is kept until the device acknowledges it
+CREATE TABLE IF NOT EXISTS mls_welcomes (
+    id BIGINT PRIMARY KEY,
+    key_package_ref BYTEA NOT NULL UNIQUE REFERENCES key_package_claims(key_package_ref) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id BIGINT NOT NULL,
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    sender_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    data BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS ix_mls_welcomes_user_device ON mls_welcomes (user_id, device_id);
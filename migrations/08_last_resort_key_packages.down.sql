@@ -0,0 +1,24 @@
+
+
+-- Claims and Welcomes of a last-resort KeyPackage used several times can not be kept,
+-- the migration is not reverted rather than deleting them
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM key_package_claims GROUP BY key_package_ref HAVING COUNT(*) > 1)
+        OR EXISTS (SELECT 1 FROM mls_welcomes GROUP BY key_package_ref HAVING COUNT(*) > 1) THEN
+        RAISE EXCEPTION 'KeyPackages have been claimed several times, the migration can not be reverted';
+    END IF;
+END
+$$;
+
+ALTER TABLE key_package_claims DROP CONSTRAINT IF EXISTS key_package_claims_pkey;
+ALTER TABLE key_package_claims ADD PRIMARY KEY (key_package_ref);
+
+ALTER TABLE mls_welcomes DROP CONSTRAINT IF EXISTS mls_welcomes_key_package_ref_chat_key;
+ALTER TABLE mls_welcomes ADD CONSTRAINT mls_welcomes_key_package_ref_key UNIQUE (key_package_ref);
+ALTER TABLE mls_welcomes ADD CONSTRAINT mls_welcomes_key_package_ref_fkey
+    FOREIGN KEY (key_package_ref) REFERENCES key_package_claims(key_package_ref) ON DELETE CASCADE;
+
+DROP INDEX IF EXISTS ux_key_packages_last_resort;
+
+ALTER TABLE key_packages DROP COLUMN IF EXISTS last_resort;
@@ -0,0 +1,14 @@
+
+
+ALTER TABLE key_packages ADD COLUMN IF NOT EXISTS last_resort BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- A device keeps a single last-resort KeyPackage, uploading a new one replaces it
+CREATE UNIQUE INDEX IF NOT EXISTS ux_key_packages_last_resort ON key_packages (user_id, device_id) WHERE last_resort;
+
+-- A last-resort KeyPackage is claimed many times, possibly by several users for several chats
+ALTER TABLE mls_welcomes DROP CONSTRAINT IF EXISTS mls_welcomes_key_package_ref_fkey;
+ALTER TABLE mls_welcomes DROP CONSTRAINT IF EXISTS mls_welcomes_key_package_ref_key;
+ALTER TABLE mls_welcomes ADD CONSTRAINT mls_welcomes_key_package_ref_chat_key UNIQUE (key_package_ref, chat_id);
+
+ALTER TABLE key_package_claims DROP CONSTRAINT IF EXISTS key_package_claims_pkey;
+ALTER TABLE key_package_claims ADD PRIMARY KEY (key_package_ref, claimed_by);
@@ -0,0 +1,3 @@
+
+
+ALTER TABLE mls_groups DROP COLUMN IF EXISTS ciphersuite;
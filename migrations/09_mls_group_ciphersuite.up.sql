@@ -0,0 +1,4 @@
+
+
+-- 1 = MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519, the only suite accepted before the policy was introduced
+ALTER TABLE mls_groups ADD COLUMN IF NOT EXISTS ciphersuite INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,12 @@
+
+
+DROP INDEX IF EXISTS ix_chat_messages_chat;
+
+DELETE FROM chat_messages WHERE content IS NULL;
+ALTER TABLE chat_messages DROP CONSTRAINT IF EXISTS chat_messages_content_or_payload;
+ALTER TABLE chat_messages DROP COLUMN IF EXISTS sender_leaf;
+ALTER TABLE chat_messages DROP COLUMN IF EXISTS epoch;
+ALTER TABLE chat_messages DROP COLUMN IF EXISTS payload;
+ALTER TABLE chat_messages ALTER COLUMN content SET NOT NULL;
+
+ALTER TABLE chats DROP COLUMN IF EXISTS encrypted;
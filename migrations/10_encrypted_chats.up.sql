@@ -0,0 +1,12 @@
+
+
+-- Encrypted chats only store MLS `PrivateMessage`s, plaintext chats (e.g. with bots) store `content`
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS encrypted BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE chat_messages ALTER COLUMN content DROP NOT NULL;
+ALTER TABLE chat_messages ADD COLUMN IF NOT EXISTS payload BYTEA DEFAULT NULL;
+ALTER TABLE chat_messages ADD COLUMN IF NOT EXISTS epoch BIGINT DEFAULT NULL;
+ALTER TABLE chat_messages ADD COLUMN IF NOT EXISTS sender_leaf INTEGER DEFAULT NULL;
+ALTER TABLE chat_messages ADD CONSTRAINT chat_messages_content_or_payload CHECK ((content IS NULL) <> (payload IS NULL));
+
+CREATE INDEX IF NOT EXISTS ix_chat_messages_chat ON chat_messages (chat_id, id);
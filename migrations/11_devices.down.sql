@@ -0,0 +1,6 @@
+
+
+ALTER TABLE mls_welcomes DROP CONSTRAINT IF EXISTS mls_welcomes_device_id_fkey;
+ALTER TABLE key_packages DROP CONSTRAINT IF EXISTS key_packages_device_id_fkey;
+
+DROP TABLE IF EXISTS devices;
@@ -0,0 +1,20 @@
+
+
+-- Devices of a user, E2EE key material, tickets and sessions are bound to them
+CREATE TABLE IF NOT EXISTS devices (
+    id BIGINT PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(64) NOT NULL,
+    platform VARCHAR(32) NOT NULL,
+    signature_key BYTEA NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS ix_devices_user ON devices (user_id);
+
+-- Rows of devices which were never registered are kept, the constraints apply to new rows
+ALTER TABLE key_packages ADD CONSTRAINT key_packages_device_id_fkey
+    FOREIGN KEY (device_id) REFERENCES devices(id) ON DELETE CASCADE NOT VALID;
+ALTER TABLE mls_welcomes ADD CONSTRAINT mls_welcomes_device_id_fkey
+    FOREIGN KEY (device_id) REFERENCES devices(id) ON DELETE CASCADE NOT VALID;
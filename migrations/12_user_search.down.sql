@@ -0,0 +1,6 @@
+
+
+DROP INDEX IF EXISTS ix_users_name_trgm;
+DROP INDEX IF EXISTS ix_users_username_trgm;
+
+ALTER TABLE users DROP COLUMN IF EXISTS discoverable;
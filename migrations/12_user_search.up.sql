@@ -0,0 +1,9 @@
+
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Users who opted out are not returned by the search, they can still be found by id
+ALTER TABLE users ADD COLUMN IF NOT EXISTS discoverable BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE INDEX IF NOT EXISTS ix_users_username_trgm ON users USING GIN (username gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS ix_users_name_trgm ON users USING GIN (name gin_trgm_ops);
@@ -0,0 +1,4 @@
+
+
+ALTER TABLE chats DROP COLUMN IF EXISTS avatar;
+ALTER TABLE users DROP COLUMN IF EXISTS avatar;
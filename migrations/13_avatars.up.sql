@@ -0,0 +1,5 @@
+
+
+-- Content hash of the avatar, its images are stored under `avatars/{hash}/`
+ALTER TABLE users ADD COLUMN IF NOT EXISTS avatar TEXT DEFAULT NULL;
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS avatar TEXT DEFAULT NULL;
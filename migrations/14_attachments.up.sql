@@ -0,0 +1,35 @@
+
+
+-- Files uploaded in chunks for a chat, the assembled content is kept in the blob store
+CREATE TABLE IF NOT EXISTS attachments (
+    id BIGINT PRIMARY KEY,
+    uploader_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    file_name TEXT NOT NULL,
+    mime_type TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    -- Lowercase hex SHA-256 of the whole file, declared by the uploader and verified on completion
+    checksum TEXT NOT NULL,
+    chunk_size INTEGER NOT NULL,
+    -- Set while the chunks are being assembled, so the upload is completed only once
+    completing BOOLEAN NOT NULL DEFAULT FALSE,
+    completed_at TIMESTAMPTZ DEFAULT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS attachment_chunks (
+    attachment_id BIGINT NOT NULL REFERENCES attachments(id) ON DELETE CASCADE,
+    chunk_index INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    PRIMARY KEY (attachment_id, chunk_index)
+);
+
+CREATE TABLE IF NOT EXISTS message_attachments (
+    message_id BIGINT NOT NULL REFERENCES chat_messages(id) ON DELETE CASCADE,
+    attachment_id BIGINT NOT NULL REFERENCES attachments(id) ON DELETE CASCADE,
+    position SMALLINT NOT NULL,
+    PRIMARY KEY (message_id, attachment_id)
+);
+
+CREATE INDEX IF NOT EXISTS ix_message_attachments_attachment ON message_attachments (attachment_id);
+CREATE INDEX IF NOT EXISTS ix_attachments_uploader_pending ON attachments (uploader_id) WHERE completed_at IS NULL;
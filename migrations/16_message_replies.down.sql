@@ -0,0 +1,8 @@
+
+
+DROP INDEX IF EXISTS ix_chat_messages_thread;
+
+ALTER TABLE chat_messages DROP COLUMN IF EXISTS quote_content;
+ALTER TABLE chat_messages DROP COLUMN IF EXISTS quote_user_id;
+ALTER TABLE chat_messages DROP COLUMN IF EXISTS thread_root_id;
+ALTER TABLE chat_messages DROP COLUMN IF EXISTS reply_to;
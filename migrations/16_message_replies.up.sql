@@ -0,0 +1,9 @@
+
+
+-- A reply keeps a snapshot of the quoted message, so the quote survives edits and deletion of the original
+ALTER TABLE chat_messages ADD COLUMN IF NOT EXISTS reply_to BIGINT DEFAULT NULL;
+ALTER TABLE chat_messages ADD COLUMN IF NOT EXISTS thread_root_id BIGINT DEFAULT NULL;
+ALTER TABLE chat_messages ADD COLUMN IF NOT EXISTS quote_user_id BIGINT DEFAULT NULL;
+ALTER TABLE chat_messages ADD COLUMN IF NOT EXISTS quote_content TEXT DEFAULT NULL;
+
+CREATE INDEX IF NOT EXISTS ix_chat_messages_thread ON chat_messages (thread_root_id, id) WHERE thread_root_id IS NOT NULL;
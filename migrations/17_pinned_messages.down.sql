@@ -0,0 +1,6 @@
+
+
+DROP TABLE IF EXISTS pinned_messages;
+
+ALTER TABLE chats DROP COLUMN IF EXISTS members_can_pin;
+ALTER TABLE chat_users DROP COLUMN IF EXISTS is_owner;
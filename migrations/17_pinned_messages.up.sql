@@ -0,0 +1,30 @@
+
+
+-- The creator owns the chat
+ALTER TABLE chat_users ADD COLUMN IF NOT EXISTS is_owner BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Chats created before get the earliest member as the owner: the first one to have sent a message
+-- (message ids grow with time), or the earliest registered user if nobody has written yet
+UPDATE chat_users SET is_owner = TRUE
+FROM (
+    SELECT DISTINCT ON (cu.chat_id) cu.chat_id, cu.user_id
+    FROM chat_users cu
+    WHERE NOT EXISTS (SELECT 1 FROM chat_users owner WHERE owner.chat_id = cu.chat_id AND owner.is_owner)
+    ORDER BY
+        cu.chat_id,
+        (SELECT MIN(m.id) FROM chat_messages m WHERE m.chat_id = cu.chat_id AND m.user_id = cu.user_id) NULLS LAST,
+        cu.user_id
+) earliest
+WHERE chat_users.chat_id = earliest.chat_id AND chat_users.user_id = earliest.user_id;
+-- Otherwise only owners can pin messages
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS members_can_pin BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE TABLE IF NOT EXISTS pinned_messages (
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    message_id BIGINT NOT NULL REFERENCES chat_messages(id) ON DELETE CASCADE,
+    pinned_by BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    pinned_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (chat_id, message_id)
+);
+
+CREATE INDEX IF NOT EXISTS ix_pinned_messages_chat_pinned_at ON pinned_messages (chat_id, pinned_at);
@@ -0,0 +1,8 @@
+
+
+ALTER TABLE chat_messages DROP COLUMN IF EXISTS forward_name;
+ALTER TABLE chat_messages DROP COLUMN IF EXISTS forward_message_id;
+ALTER TABLE chat_messages DROP COLUMN IF EXISTS forward_chat_id;
+ALTER TABLE chat_messages DROP COLUMN IF EXISTS forward_user_id;
+
+ALTER TABLE users DROP COLUMN IF EXISTS link_forwards;
@@ -0,0 +1,11 @@
+
+
+-- Users who opted out are shown by name only on forwarded copies of their messages
+ALTER TABLE users ADD COLUMN IF NOT EXISTS link_forwards BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- Origin of a forwarded message, taken when it was forwarded. The name is always set on forwarded messages,
+-- the ids are NULL when the author has not allowed linking
+ALTER TABLE chat_messages ADD COLUMN IF NOT EXISTS forward_user_id BIGINT DEFAULT NULL;
+ALTER TABLE chat_messages ADD COLUMN IF NOT EXISTS forward_chat_id BIGINT DEFAULT NULL;
+ALTER TABLE chat_messages ADD COLUMN IF NOT EXISTS forward_message_id BIGINT DEFAULT NULL;
+ALTER TABLE chat_messages ADD COLUMN IF NOT EXISTS forward_name TEXT DEFAULT NULL;
@@ -0,0 +1,5 @@
+
+
+DROP TABLE IF EXISTS message_mentions;
+
+ALTER TABLE chat_users DROP COLUMN IF EXISTS muted;
@@ -0,0 +1,12 @@
+
+
+-- Muted chats deliver messages without notifying, except for messages mentioning the member
+ALTER TABLE chat_users ADD COLUMN IF NOT EXISTS muted BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS message_mentions (
+    message_id BIGINT NOT NULL REFERENCES chat_messages(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    PRIMARY KEY (message_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS ix_message_mentions_user ON message_mentions (user_id, message_id);
@@ -0,0 +1,4 @@
+
+
+-- Formatting of plaintext messages, offsets and lengths are in UTF-16 code units of the content
+ALTER TABLE chat_messages ADD COLUMN IF NOT EXISTS entities JSONB NOT NULL DEFAULT '[]';
@@ -0,0 +1,5 @@
+
+
+DROP INDEX IF EXISTS ix_chat_messages_search;
+
+ALTER TABLE chat_messages DROP COLUMN IF EXISTS search_vector;
@@ -0,0 +1,7 @@
+
+
+-- The `simple` configuration does not stem, so messages in any language are matched by whole words
+ALTER TABLE chat_messages ADD COLUMN IF NOT EXISTS search_vector TSVECTOR
+    GENERATED ALWAYS AS (to_tsvector('simple', COALESCE(content, ''))) STORED;
+
+CREATE INDEX IF NOT EXISTS ix_chat_messages_search ON chat_messages USING GIN (search_vector);
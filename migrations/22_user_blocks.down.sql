@@ -0,0 +1,6 @@
+
+
+DROP TABLE IF EXISTS direct_chats;
+ALTER TABLE chats DROP COLUMN IF EXISTS is_direct;
+
+DROP TABLE IF EXISTS user_blocks;
@@ -0,0 +1,36 @@
+
+
+CREATE TABLE IF NOT EXISTS user_blocks (
+    blocker_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    blocked_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (blocker_id, blocked_id),
+    CHECK (blocker_id <> blocked_id)
+);
+
+CREATE INDEX IF NOT EXISTS ix_user_blocks_blocked ON user_blocks (blocked_id);
+
+-- A direct chat is between exactly two users, a block by one of them stops the conversation
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS is_direct BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- The direct chat of every pair of users, `first_user_id` is the lower id
+CREATE TABLE IF NOT EXISTS direct_chats (
+    chat_id BIGINT PRIMARY KEY REFERENCES chats(id) ON DELETE CASCADE,
+    first_user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    second_user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    CHECK (first_user_id < second_user_id)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS ux_direct_chats_users ON direct_chats (first_user_id, second_user_id);
+
+-- Chats of two members created before were used as direct chats, the oldest one of a pair is registered for it
+UPDATE chats SET is_direct = TRUE
+WHERE id IN (SELECT chat_id FROM chat_users GROUP BY chat_id HAVING COUNT(*) = 2);
+
+INSERT INTO direct_chats (chat_id, first_user_id, second_user_id)
+SELECT chat_id, MIN(user_id), MAX(user_id)
+FROM chat_users
+WHERE chat_id IN (SELECT id FROM chats WHERE is_direct)
+GROUP BY chat_id
+ORDER BY chat_id
+ON CONFLICT DO NOTHING;
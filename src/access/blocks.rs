@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use crate::{
+    app_state::AppState,
+    models::{block::Block, chat::{Chat, ChatUser}, errors::ModelError, user::UserID}
+};
+
+
+/// Fails if a future member of the chat has blocked the user adding them
+pub async fn check_can_add(state: &Arc<AppState>, user_id: UserID, member_ids: &[UserID]) -> Result<(), ModelError> {
+    let blockers = Block::blockers_among(user_id, member_ids, &state.pool).await?;
+    if !blockers.is_empty() {
+        return Err(ModelError::ClientError("Some of the users can not be added to a chat by you".to_string()))
+    }
+    Ok(())
+}
+
+/// Fails if the chat is a direct chat and the other member has blocked the user.
+/// Covers everything a member adds to the chat: messages, reactions and pins.
+pub async fn check_can_post(state: &Arc<AppState>, user_id: UserID, chat: &Chat) -> Result<(), ModelError> {
+    if !chat.is_direct {
+        return Ok(())
+    }
+    let member_ids = ChatUser::member_ids(chat.id, &state.pool).await?;
+    let peer_ids: Vec<UserID> = member_ids.into_iter().filter(|member_id| *member_id != user_id).collect();
+    let blockers = Block::blockers_among(user_id, &peer_ids, &state.pool).await?;
+    if !blockers.is_empty() {
+        return Err(ModelError::ClientError("You can not send messages to this user".to_string()))
+    }
+    Ok(())
+}
+
+/// Mentioned users who have not blocked the sender, only they are notified
+pub async fn notified_mentions(state: &Arc<AppState>, sender_id: UserID, mention_ids: Vec<UserID>) -> Result<Vec<UserID>, ModelError> {
+    if mention_ids.is_empty() {
+        return Ok(mention_ids)
+    }
+    let blockers = Block::blockers_among(sender_id, &mention_ids, &state.pool).await?;
+    Ok(mention_ids.into_iter().filter(|mentioned_id| !blockers.contains(mentioned_id)).collect())
+}
+
+/// Users that must not be shown to the viewer in search results
+pub async fn hidden_user_ids(state: &Arc<AppState>, viewer_id: UserID) -> Result<Vec<UserID>, ModelError> {
+    Block::blocked_ids(viewer_id, &state.pool).await
+}
@@ -1,10 +1,11 @@
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::{broadcast, RwLock};
 
-use crate::{auth::ticket::TicketService, cache::cache::TimedCache, models::{prelude::SnowflakeGenerator, AppPool}};
+use crate::{auth::ticket::TicketService, bus::bus::EventBusBackend, models::{device::DeviceID, prelude::SnowflakeGenerator, user::UserID, AppPool}, storage::storage::StorageBackend};
 
 
-pub type Clients = Arc<RwLock<HashMap<i64, broadcast::Sender<String>>>>;
+/// Channels of the connected devices by user, every socket of a device subscribes to the device's channel
+pub type Clients = Arc<RwLock<HashMap<UserID, HashMap<DeviceID, broadcast::Sender<String>>>>>;
 pub type Users = Arc<RwLock<HashMap<i64, String>>>;
 
 
@@ -13,7 +14,8 @@ pub struct AppState {
     pub clients: Clients,
     pub tickets: Arc<TicketService>,
     pub users: Users,
-    pub tx: broadcast::Sender<String>,
+    pub bus: Arc<EventBusBackend>,
+    pub storage: Arc<StorageBackend>,
     pub snowflake_generator: Arc<SnowflakeGenerator>,
     pub pool: AppPool,
 }
\ No newline at end of file
@@ -4,7 +4,7 @@ use serde::Deserialize;
 
 use crate::{
     app_state::AppState, auth::{jwt_authorization::{JWTAuthorize, JWTResponse, JWToken},
-    password::{generate_password_hash, verify_password}, ticket::TicketQuery}, core::ErrorResponse, models::{errors::ModelError,user::{NewUser, User}}
+    password::{generate_password_hash, verify_password}, ticket::{TicketBinding, TicketForm, TicketInfo, TicketQuery}}, core::ErrorResponse, models::{device::Device, errors::ModelError,user::{NewUser, User}}
 };
 
 
@@ -21,6 +21,12 @@ pub struct LoginForm {
     pub password: String
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordForm {
+    pub password: String,
+    pub new_password: String
+}
+
 #[axum::debug_handler]
 pub async fn login(
     State(state): State<Arc<AppState>>,
@@ -78,11 +84,22 @@ pub async fn sign_up(
 }
 
 
+/// Issues a WebSocket ticket for one of the user's devices
 pub async fn ticket(
     JWTAuthorize(jwt): JWTAuthorize,
-    State(state): State<Arc<AppState>>
-) -> impl IntoResponse {
-    (StatusCode::CREATED, state.tickets.generate(jwt.claims.user_id).await)
+    binding: TicketBinding,
+    State(state): State<Arc<AppState>>,
+    Json(form): Json<TicketForm>,
+) -> Response {
+    match Device::find(form.device_id, jwt.claims.user_id, &state.pool).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return (StatusCode::NOT_FOUND, Json(ErrorResponse{ error: "Device not found".to_string() })).into_response(),
+        Err(err) => return ModelError::into_error_response(err, None, None),
+    }
+    match state.tickets.generate(jwt.claims.user_id, form.device_id, binding).await {
+        Ok(ticket) => (StatusCode::CREATED, ticket).into_response(),
+        Err(err) => (StatusCode::TOO_MANY_REQUESTS, Json(ErrorResponse{ error: err.to_string() })).into_response()
+    }
 }
 
 
@@ -95,4 +112,62 @@ pub async fn revoke_ticket (
         Ok(_) => (StatusCode::OK, Json(ErrorResponse { error: format!("Ticket `{}` has been successfully revoked", &ticket_query.ticket) })).into_response(),
         Err(err) => (StatusCode::BAD_REQUEST, Json(ErrorResponse{ error: err.to_string() })).into_response()
     }
+}
+
+
+pub async fn list_tickets(
+    JWTAuthorize(jwt): JWTAuthorize,
+    State(state): State<Arc<AppState>>
+) -> Json<Vec<TicketInfo>> {
+    Json(state.tickets.list(jwt.claims.user_id).await)
+}
+
+
+pub async fn revoke_all_tickets(
+    JWTAuthorize(jwt): JWTAuthorize,
+    State(state): State<Arc<AppState>>
+) -> Response {
+    let revoked = state.tickets.revoke_all(jwt.claims.user_id).await;
+    (StatusCode::OK, Json(ErrorResponse { error: format!("{} ticket(s) have been successfully revoked", revoked) })).into_response()
+}
+
+
+pub async fn logout(
+    JWTAuthorize(jwt): JWTAuthorize,
+    State(state): State<Arc<AppState>>
+) -> StatusCode {
+    let revoked = state.tickets.revoke_all(jwt.claims.user_id).await;
+    tracing::debug!("Logout ({}): {} ticket(s) revoked", jwt.claims.user_id, revoked);
+    StatusCode::NO_CONTENT
+}
+
+
+pub async fn change_password(
+    JWTAuthorize(jwt): JWTAuthorize,
+    State(state): State<Arc<AppState>>,
+    Json(form): Json<ChangePasswordForm>,
+) -> Result<StatusCode, Response> {
+    let user_id = jwt.claims.user_id;
+    let dto = User::find_password_hash(user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .ok_or_else(|| {
+            let error = Json(ErrorResponse { error: "User not found".to_string() });
+            (StatusCode::UNAUTHORIZED, error).into_response()
+        })?;
+
+    if !verify_password(form.password, dto.hashed_password) {
+        let error = Json(ErrorResponse {
+            error: "Wrong password".to_string()
+        });
+        return Err((StatusCode::UNAUTHORIZED, error).into_response())
+    }
+
+    User::update_password(user_id, generate_password_hash(form.new_password), &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+
+    let revoked = state.tickets.revoke_all(user_id).await;
+    tracing::debug!("Password changed ({}): {} ticket(s) revoked", user_id, revoked);
+    Ok(StatusCode::NO_CONTENT)
 }
\ No newline at end of file
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use axum::{extract::{Path, State}, http::StatusCode, response::Response, Json};
+use serde::Deserialize;
+
+use crate::{
+    app_state::AppState,
+    auth::jwt_authorization::JWTAuthorize,
+    bus::bus::{ClusterEvent, EventBus},
+    core::Base64,
+    models::{device::{Device, DeviceID, NewDevice}, errors::ModelError},
+    settings::{DEVICE_MAX_PER_USER, DEVICE_NAME_MAX_LENGTH, DEVICE_PLATFORM_MAX_LENGTH, DEVICE_SIGNATURE_KEY_MAX_LENGTH}
+};
+
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterDeviceForm {
+    pub name: String,
+    pub platform: String,
+    /// Public key of the device's MLS signature key pair
+    pub signature_key: Base64,
+}
+
+
+pub async fn register_device(
+    JWTAuthorize(jwt): JWTAuthorize,
+    State(state): State<Arc<AppState>>,
+    Json(form): Json<RegisterDeviceForm>,
+) -> Result<(StatusCode, Json<Device>), Response> {
+    let user_id = jwt.claims.user_id;
+    let client_error = |error: String| ModelError::into_error_response(ModelError::ClientError(error), None, None);
+
+    let name = form.name.trim();
+    let platform = form.platform.trim();
+    if name.is_empty() || name.chars().count() > DEVICE_NAME_MAX_LENGTH {
+        return Err(client_error(format!("Device name must contain from 1 to {} characters", DEVICE_NAME_MAX_LENGTH)));
+    }
+    if platform.is_empty() || platform.chars().count() > DEVICE_PLATFORM_MAX_LENGTH {
+        return Err(client_error(format!("Platform must contain from 1 to {} characters", DEVICE_PLATFORM_MAX_LENGTH)));
+    }
+    let Base64(signature_key) = form.signature_key;
+    if signature_key.is_empty() || signature_key.len() > DEVICE_SIGNATURE_KEY_MAX_LENGTH {
+        return Err(client_error("Invalid signature key".to_string()));
+    }
+
+    let device = NewDevice {
+        id: state.snowflake_generator.generate_id().await,
+        user_id,
+        name: name.to_string(),
+        platform: platform.to_string(),
+        signature_key,
+    }
+        .insert(DEVICE_MAX_PER_USER as i64, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, Some(StatusCode::CONFLICT), None))?
+        .ok_or_else(|| client_error(format!("A user can not have more than {} devices", DEVICE_MAX_PER_USER)))?;
+
+    tracing::debug!("Device registered ({}): {}", user_id, device.id);
+    Ok((StatusCode::CREATED, Json(device)))
+}
+
+
+pub async fn list_devices(
+    JWTAuthorize(jwt): JWTAuthorize,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<Device>>, Response> {
+    let devices = Device::list(jwt.claims.user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    Ok(Json(devices))
+}
+
+
+/// Removes the device with its KeyPackages and pending Welcomes and revokes its tickets
+pub async fn remove_device(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path(device_id): Path<DeviceID>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response> {
+    let user_id = jwt.claims.user_id;
+    let removed = Device::delete(device_id, user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !removed {
+        let err = ModelError::ClientError("Device not found".to_string());
+        return Err(ModelError::into_error_response(err, Some(StatusCode::NOT_FOUND), None))
+    }
+
+    let revoked = state.tickets.revoke_device(user_id, device_id).await;
+    // Sockets already open on any instance are closed
+    state.bus.publish(ClusterEvent::DeviceRemoved { user_id, device_id }).await;
+    tracing::debug!("Device removed ({}): {}, {} ticket(s) revoked", user_id, device_id, revoked);
+    Ok(StatusCode::NO_CONTENT)
+}
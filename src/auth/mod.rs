@@ -1,4 +1,5 @@
 pub mod auth;
+pub mod devices;
 
 
 pub mod password {
@@ -27,28 +28,128 @@ pub mod password {
 
 
 pub mod ticket {
+    use std::net::{IpAddr, SocketAddr};
+
+    use axum::{
+        extract::{ConnectInfo, FromRequestParts},
+        http::{header::USER_AGENT, request::Parts, StatusCode},
+    };
+    use chrono::{DateTime, Utc};
     use rand::{distr::Alphanumeric, Rng};
+    use serde::{Deserialize, Serialize};
     use tokio::{sync::RwLock, task};
 
-    use crate::{cache::cache::{Cache, TimedCache}, models::user::UserID, settings::TICKET_LENGTH};
+    use crate::{
+        cache::cache::{Cache, CacheBackend},
+        models::{device::DeviceID, user::UserID, AppPool},
+        settings::{TICKET_LENGTH, WS_TICKET_BIND_IP, WS_TICKET_BIND_USER_AGENT, WS_TICKET_LIFETIME, WS_TICKET_MAX_PER_USER}
+    };
 
     
     #[derive(serde::Deserialize)]
     pub struct TicketQuery {
         pub ticket: String,
     }
-    pub struct TicketService(Tickets);
-    
-    type Tickets = RwLock<TimedCache<String, UserID>>;
 
-    impl Default for TicketService {
-        fn default() -> Self {
-            Self(RwLock::new(TimedCache::new()))
+    #[derive(serde::Deserialize)]
+    pub struct TicketForm {
+        pub device_id: DeviceID,
+    }
+
+    /// Client fingerprint a ticket is bound to when it is issued.
+    /// Only the parts enabled in settings are stored and compared.
+    #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+    pub struct TicketBinding {
+        pub ip: Option<IpAddr>,
+        pub user_agent: Option<String>,
+    }
+
+    impl TicketBinding {
+        pub fn new(ip: IpAddr, user_agent: Option<String>) -> Self {
+            Self {
+                ip: WS_TICKET_BIND_IP.then_some(ip),
+                user_agent: if *WS_TICKET_BIND_USER_AGENT { user_agent } else { None },
+            }
+        }
+    }
+
+    impl<S> FromRequestParts<S> for TicketBinding
+    where
+        S: Send + Sync,
+    {
+        type Rejection = (StatusCode, &'static str);
+
+        async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+            let ConnectInfo(addr) = parts.extensions.get::<ConnectInfo<SocketAddr>>()
+                .ok_or_else(|| {
+                    tracing::error!("`ConnectInfo` is missing, the app must be served with `into_make_service_with_connect_info`");
+                    (StatusCode::INTERNAL_SERVER_ERROR, "Internal error")
+                })?;
+            let user_agent = parts.headers.get(USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+
+            Ok(Self::new(addr.ip(), user_agent))
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct TicketRecord {
+        pub user_id: UserID,
+        pub device_id: DeviceID,
+        pub binding: TicketBinding,
+    }
+
+    /// Outstanding ticket as it is shown to its owner
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct TicketInfo {
+        pub ticket: String,
+        pub device_id: DeviceID,
+        pub created_at: DateTime<Utc>,
+        pub expires_at: DateTime<Utc>,
+    }
+
+    /// The lock makes ticket operations atomic within one instance.
+    /// Consuming a ticket stays atomic across instances, as the backend removes a record in one step,
+    /// while the per-user cap may be slightly exceeded under concurrent requests to different instances.
+    pub struct TicketService(RwLock<Tickets>);
+
+    struct Tickets {
+        records: CacheBackend<String, TicketRecord>,
+        // Reverse index of tickets issued per user
+        issued: CacheBackend<UserID, Vec<TicketInfo>>,
+    }
+
+    impl Tickets {
+        /// Returns tickets of the user which are still alive
+        async fn outstanding(&self, user_id: UserID) -> Vec<TicketInfo> {
+            let mut tickets = self.issued.get(&user_id).await.unwrap_or_default();
+            let now = Utc::now();
+            tickets.retain(|info| info.expires_at > now);
+            tickets
+        }
+
+        async fn forget(&self, user_id: UserID, ticket: &String) {
+            let mut tickets = self.outstanding(user_id).await;
+            tickets.retain(|info| &info.ticket != ticket);
+            if tickets.is_empty() {
+                self.issued.remove(&user_id).await;
+            } else {
+                self.issued.set(user_id, tickets, Some(*WS_TICKET_LIFETIME)).await;
+            }
         }
     }
 
     impl TicketService {
-        pub async fn generate(&self, user_id: UserID) -> String {
+        pub async fn new(pool: &AppPool) -> Self {
+            Self(RwLock::new(Tickets {
+                records: CacheBackend::from_settings("ticket", pool).await,
+                issued: CacheBackend::from_settings("user_tickets", pool).await,
+            }))
+        }
+
+
+        pub async fn generate(&self, user_id: UserID, device_id: DeviceID, binding: TicketBinding) -> Result<String, &str> {
             let ticket = task::spawn_blocking(|| {
                 let ticket: String = rand::rng()
                     .sample_iter(&Alphanumeric)
@@ -60,30 +161,59 @@ pub mod ticket {
 
             {
                 let storage = self.0.write().await;
-                storage.set(ticket.clone(), user_id, None);
+                let mut tickets = storage.outstanding(user_id).await;
+                if tickets.len() >= *WS_TICKET_MAX_PER_USER {
+                    return Err("Too many outstanding tickets")
+                }
+                storage.records.set(ticket.clone(), TicketRecord { user_id, device_id, binding }, Some(*WS_TICKET_LIFETIME)).await;
+                let created_at = Utc::now();
+                tickets.push(TicketInfo {
+                    ticket: ticket.clone(),
+                    device_id,
+                    created_at,
+                    expires_at: created_at + *WS_TICKET_LIFETIME,
+                });
+                storage.issued.set(user_id, tickets, Some(*WS_TICKET_LIFETIME)).await;
             }
             
-            ticket
+            Ok(ticket)
         }
 
-        pub async fn verify(&self, ticket: &String) -> Option<UserID> {
-            let storage = self.0.read().await;
-            storage.get(ticket)
+        /// Atomically removes the ticket and returns its owner and the device it was issued to.
+        /// A ticket presented from another client is burned as well, so it can not be retried.
+        pub async fn consume(&self, ticket: &String, binding: &TicketBinding) -> Result<(UserID, DeviceID), &str> {
+            let storage = self.0.write().await;
+            let record = storage.records.remove(ticket).await
+                .ok_or("Invalid ticket")?;
+            storage.forget(record.user_id, ticket).await;
+
+            if let Some(ip) = record.binding.ip && binding.ip != Some(ip) {
+                tracing::warn!("Ticket of user `{}` was presented from another IP ({:?})", record.user_id, binding.ip);
+                return Err("Ticket was issued to another client")
+            }
+            if record.binding.user_agent.is_some() && record.binding.user_agent != binding.user_agent {
+                tracing::warn!("Ticket of user `{}` was presented with another User-Agent", record.user_id);
+                return Err("Ticket was issued to another client")
+            }
+            Ok((record.user_id, record.device_id))
         }
 
         pub async fn remove(&self, ticket: &String) -> Option<UserID> {
             let storage = self.0.write().await;
-            storage.remove(&ticket)
+            let record = storage.records.remove(ticket).await?;
+            storage.forget(record.user_id, ticket).await;
+            Some(record.user_id)
         }
 
         pub async fn validated_remove(&self, user_id: UserID, ticket: &String) -> Result<(), &str>  {
             let storage = self.0.write().await;
-            let ticket_owner_id = storage.get(ticket)
-                .ok_or("Ticket not found")?;
+            let ticket_owner_id = storage.records.get(ticket).await
+                .ok_or("Ticket not found")?
+                .user_id;
             if user_id != ticket_owner_id {
                 return Err("Token does not belong to the current use")
             };
-            storage.remove(ticket)
+            storage.records.remove(ticket).await
                 .ok_or({
                     tracing::error!(
                         "`validated_remove' (user_id: `{}`, ticket: `{}`)\nError: 'storage.remove() returned None, but previous validations passed successfully'",
@@ -92,8 +222,42 @@ pub mod ticket {
                     );
                     "Internal error"
             })?;
+            storage.forget(user_id, ticket).await;
             Ok(())
         }
+
+        pub async fn list(&self, user_id: UserID) -> Vec<TicketInfo> {
+            let storage = self.0.read().await;
+            storage.outstanding(user_id).await
+        }
+
+        /// Revokes every outstanding ticket of the user, returns how many were revoked
+        pub async fn revoke_all(&self, user_id: UserID) -> usize {
+            let storage = self.0.write().await;
+            let tickets = storage.outstanding(user_id).await;
+            for info in tickets.iter() {
+                storage.records.remove(&info.ticket).await;
+            }
+            storage.issued.remove(&user_id).await;
+            tickets.len()
+        }
+
+        /// Revokes the outstanding tickets issued to the device, returns how many were revoked
+        pub async fn revoke_device(&self, user_id: UserID, device_id: DeviceID) -> usize {
+            let storage = self.0.write().await;
+            let (revoked, kept): (Vec<_>, Vec<_>) = storage.outstanding(user_id).await
+                .into_iter()
+                .partition(|info| info.device_id == device_id);
+            for info in revoked.iter() {
+                storage.records.remove(&info.ticket).await;
+            }
+            if kept.is_empty() {
+                storage.issued.remove(&user_id).await;
+            } else {
+                storage.issued.set(user_id, kept, Some(*WS_TICKET_LIFETIME)).await;
+            }
+            revoked.len()
+        }
             
     }
 
@@ -0,0 +1,110 @@
+use std::{future::Future, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::{
+    bus::postgres::PgEventBus,
+    models::{chat::ChatID, device::DeviceID, message::MessageID, user::UserID, AppPool},
+    settings::{EVENT_BUS, EVENT_BUS_CAPACITY}
+};
+
+
+/// Event shared between all server instances.
+/// Events only point to stored records, every instance loads the record itself
+/// (`NOTIFY` payloads are limited to 8000 bytes).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClusterEvent {
+    MessageCreated { chat_id: ChatID, message_id: MessageID },
+    MlsMessageCreated { chat_id: ChatID, message_id: i64 },
+    WelcomeCreated { user_id: UserID, welcome_id: i64 },
+    KeyPackageStockLow { user_id: UserID, device_id: DeviceID, stock: i64 },
+    DeviceRemoved { user_id: UserID, device_id: DeviceID },
+    ProfileUpdated { user_id: UserID },
+    ChatUpdated { chat_id: ChatID },
+    /// The emoji is small enough to travel with the event
+    ReactionChanged { chat_id: ChatID, message_id: MessageID, user_id: UserID, emoji: String, added: bool },
+    PinChanged { chat_id: ChatID, message_id: MessageID, user_id: UserID, pinned: bool },
+}
+
+pub trait EventBus {
+    /// Delivers the event to subscribers of every instance, including this one
+    fn publish(&self, event: ClusterEvent) -> impl Future<Output = ()> + Send;
+    fn subscribe(&self) -> broadcast::Receiver<ClusterEvent>;
+}
+
+
+/// Bus for a single server instance
+pub struct InProcessEventBus {
+    tx: broadcast::Sender<ClusterEvent>,
+}
+
+impl InProcessEventBus {
+    pub fn new() -> Self {
+        let (tx, _rx) = broadcast::channel(EVENT_BUS_CAPACITY);
+        Self { tx }
+    }
+}
+
+impl EventBus for InProcessEventBus {
+    async fn publish(&self, event: ClusterEvent) {
+        // No subscribers is not an error, there may be no connected clients
+        let _ = self.tx.send(event);
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<ClusterEvent> {
+        self.tx.subscribe()
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventBusKind {
+    InProcess,
+    Postgres,
+}
+
+impl FromStr for EventBusKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "in_process" | "memory" => Ok(Self::InProcess),
+            "postgres" => Ok(Self::Postgres),
+            _ => Err(format!("Unknown event bus `{}`", s))
+        }
+    }
+}
+
+
+/// Event bus selected by the `EVENT_BUS` setting
+pub enum EventBusBackend {
+    InProcess(InProcessEventBus),
+    Postgres(PgEventBus),
+}
+
+impl EventBusBackend {
+    pub async fn from_settings(pool: &AppPool) -> Self {
+        match *EVENT_BUS {
+            EventBusKind::InProcess => Self::InProcess(InProcessEventBus::new()),
+            EventBusKind::Postgres => Self::Postgres(PgEventBus::listen(pool.clone()).await),
+        }
+    }
+}
+
+impl EventBus for EventBusBackend {
+    async fn publish(&self, event: ClusterEvent) {
+        match self {
+            Self::InProcess(bus) => bus.publish(event).await,
+            Self::Postgres(bus) => bus.publish(event).await,
+        }
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<ClusterEvent> {
+        match self {
+            Self::InProcess(bus) => bus.subscribe(),
+            Self::Postgres(bus) => bus.subscribe(),
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::time::{Duration, Instant};
+
+use sqlx::postgres::PgListener;
+use tokio::sync::broadcast;
+
+use crate::{
+    bus::bus::{ClusterEvent, EventBus},
+    models::AppPool,
+    settings::{EVENT_BUS_CAPACITY, EVENT_BUS_CHANNEL, EVENT_BUS_RECONNECT_MAX_DELAY}
+};
+
+
+/// Bus shared by all instances connected to the same database through `LISTEN/NOTIFY`.
+/// Published events come back through the listener, so local subscribers receive them exactly once.
+pub struct PgEventBus {
+    pool: AppPool,
+    tx: broadcast::Sender<ClusterEvent>,
+}
+
+impl PgEventBus {
+    pub async fn listen(pool: AppPool) -> Self {
+        let (tx, _rx) = broadcast::channel(EVENT_BUS_CAPACITY);
+
+        let mut listener = PgListener::connect_with(&pool).await.unwrap();
+        listener.listen(EVENT_BUS_CHANNEL).await.unwrap();
+
+        let local_tx = tx.clone();
+        tokio::spawn(async move {
+            let mut delay = Duration::ZERO;
+            let mut disconnected_at: Option<Instant> = None;
+            loop {
+                // `recv` reconnects by itself, notifications sent while disconnected are lost
+                let notification = match listener.recv().await {
+                    Ok(_notification) => _notification,
+                    Err(e) => {
+                        if disconnected_at.is_none() {
+                            tracing::error!("Event bus disconnected, events are lost until it reconnects: {:?}", e);
+                            disconnected_at = Some(Instant::now());
+                        }
+                        delay = (delay * 2).clamp(Duration::from_millis(100), EVENT_BUS_RECONNECT_MAX_DELAY);
+                        tokio::time::sleep(delay).await;
+                        continue;
+                    }
+                };
+                if let Some(since) = disconnected_at.take() {
+                    tracing::warn!("Event bus reconnected, events published during the last {:?} were lost", since.elapsed());
+                    delay = Duration::ZERO;
+                }
+                match serde_json::from_str::<ClusterEvent>(notification.payload()) {
+                    Ok(event) => { let _ = local_tx.send(event); },
+                    Err(e) => tracing::error!("Invalid cluster event `{}`: {:?}", notification.payload(), e),
+                }
+            }
+        });
+
+        Self { pool, tx }
+    }
+}
+
+impl EventBus for PgEventBus {
+    async fn publish(&self, event: ClusterEvent) {
+        let payload = serde_json::to_string(&event).unwrap();
+        let result = sqlx::query!("SELECT pg_notify($1, $2)", EVENT_BUS_CHANNEL, payload)
+            .execute(&self.pool)
+            .await;
+
+        if let Err(e) = result {
+            tracing::error!("{:?}", e);
+        }
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<ClusterEvent> {
+        self.tx.subscribe()
+    }
+}
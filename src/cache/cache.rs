@@ -1,13 +1,25 @@
 use timedmap::TimedMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::str::FromStr;
 use std::time::Duration;
 use std::hash::Hash;
 
-use crate::settings::DEFAULT_RECORD_LIFETIME;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::cache::{postgres::PgCache, resp::RespCache};
+use crate::models::AppPool;
+use crate::settings::{CACHE_BACKEND, CACHE_URL, DEFAULT_RECORD_LIFETIME};
 
 pub trait Cache<T, U> {
-    fn get(&self, key: &T) -> Option<U>;
-    fn set(&self, key: T, value: U, lifetime: Option<Duration>);
-    fn remove(&self, key: &T) -> Option<U>;
+    fn get(&self, key: &T) -> impl Future<Output = Option<U>> + Send;
+    fn set(&self, key: T, value: U, lifetime: Option<Duration>) -> impl Future<Output = ()> + Send;
+    /// Removes the record and returns its value in a single atomic step
+    fn remove(&self, key: &T) -> impl Future<Output = Option<U>> + Send;
+}
+
+pub fn get_default_lifetime() -> Duration {
+    DEFAULT_RECORD_LIFETIME
 }
 
 #[derive(Debug)]
@@ -17,37 +29,106 @@ pub struct TimedCache<T, U> {
 
 impl<T, U> TimedCache<T, U> {
     pub fn new() -> Self {
-        TimedCache { 
+        TimedCache {
             storage: TimedMap::new()
         }
     }
-
-    fn get_default_lifetime() -> Duration {
-        DEFAULT_RECORD_LIFETIME.clone()
-    }
 }
 
 
 impl<T, U> Cache<T, U> for TimedCache<T, U>
 where
-    T: Eq + PartialEq + Hash + Clone,
-    U: Clone, 
+    T: Eq + PartialEq + Hash + Clone + Send + Sync,
+    U: Clone + Send + Sync,
 {
-    fn get(&self, key: &T) -> Option<U> {
+    async fn get(&self, key: &T) -> Option<U> {
         self.storage.get(&key)
     }
 
-    fn set(&self, key: T, value: U, lifetime: Option<Duration>) {
+    async fn set(&self, key: T, value: U, lifetime: Option<Duration>) {
         self.storage.insert(
             key,
             value,
             match lifetime {
                 Some(_lifetime) => _lifetime,
-                None => Self::get_default_lifetime()
+                None => get_default_lifetime()
             });
     }
-    
-    fn remove(&self, key: &T) -> Option<U> {
+
+    async fn remove(&self, key: &T) -> Option<U> {
         self.storage.remove(key)
     }
 }
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheBackendKind {
+    Memory,
+    Postgres,
+    Resp,
+}
+
+impl FromStr for CacheBackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "memory" => Ok(Self::Memory),
+            "postgres" => Ok(Self::Postgres),
+            "resp" | "redis" => Ok(Self::Resp),
+            _ => Err(format!("Unknown cache backend `{}`", s))
+        }
+    }
+}
+
+
+/// Cache selected by the `CACHE_BACKEND` setting.
+/// Only the distributed backends can be shared between several server instances.
+pub enum CacheBackend<T, U> {
+    Memory(TimedCache<T, U>),
+    Postgres(PgCache<T, U>),
+    Resp(RespCache<T, U>),
+}
+
+impl<T, U> CacheBackend<T, U> {
+    /// `namespace` prefixes every key, so several caches can share one distributed store
+    pub async fn from_settings(namespace: &'static str, pool: &AppPool) -> Self {
+        match *CACHE_BACKEND {
+            CacheBackendKind::Memory => Self::Memory(TimedCache::new()),
+            CacheBackendKind::Postgres => Self::Postgres(PgCache::new(namespace, pool.clone())),
+            CacheBackendKind::Resp => Self::Resp(
+                RespCache::connect(namespace, &CACHE_URL).await.unwrap_or_else(|e| panic!("{}", e))
+            ),
+        }
+    }
+}
+
+impl<T, U> Cache<T, U> for CacheBackend<T, U>
+where
+    T: Eq + PartialEq + Hash + Clone + Display + Send + Sync,
+    U: Clone + Serialize + DeserializeOwned + Send + Sync,
+{
+    async fn get(&self, key: &T) -> Option<U> {
+        match self {
+            Self::Memory(cache) => cache.get(key).await,
+            Self::Postgres(cache) => cache.get(key).await,
+            Self::Resp(cache) => cache.get(key).await,
+        }
+    }
+
+    async fn set(&self, key: T, value: U, lifetime: Option<Duration>) {
+        match self {
+            Self::Memory(cache) => cache.set(key, value, lifetime).await,
+            Self::Postgres(cache) => cache.set(key, value, lifetime).await,
+            Self::Resp(cache) => cache.set(key, value, lifetime).await,
+        }
+    }
+
+    async fn remove(&self, key: &T) -> Option<U> {
+        match self {
+            Self::Memory(cache) => cache.remove(key).await,
+            Self::Postgres(cache) => cache.remove(key).await,
+            Self::Resp(cache) => cache.remove(key).await,
+        }
+    }
+}
@@ -1 +1,3 @@
-pub mod cache;
\ No newline at end of file
+pub mod cache;
+pub mod postgres;
+pub mod resp;
@@ -0,0 +1,122 @@
+use std::{fmt::Display, marker::PhantomData, time::Duration};
+
+use chrono::Utc;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{cache::cache::{get_default_lifetime, Cache}, models::AppPool, settings::CACHE_PURGE_INTERVAL};
+
+
+/// Cache stored in the `UNLOGGED` table `cache_entries`.
+/// Expired records are invisible to reads and get deleted by [`spawn_purge_task`].
+pub struct PgCache<T, U> {
+    namespace: &'static str,
+    pool: AppPool,
+    _marker: PhantomData<fn() -> (T, U)>,
+}
+
+impl<T, U> PgCache<T, U> {
+    pub fn new(namespace: &'static str, pool: AppPool) -> Self {
+        Self {
+            namespace,
+            pool,
+            _marker: PhantomData,
+        }
+    }
+
+    fn key(&self, key: &impl Display) -> String {
+        format!("{}:{}", self.namespace, key)
+    }
+}
+
+
+impl<T, U> Cache<T, U> for PgCache<T, U>
+where
+    T: Display + Send + Sync,
+    U: Serialize + DeserializeOwned + Send + Sync,
+{
+    async fn get(&self, key: &T) -> Option<U> {
+        let result = sqlx::query_scalar!(
+            "SELECT value FROM cache_entries WHERE key = $1 AND expires_at > NOW()",
+            self.key(key)
+        )
+        .fetch_optional(&self.pool)
+        .await;
+
+        match result {
+            Ok(value) => value.and_then(|_value| serde_json::from_value(_value).ok()),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                None
+            }
+        }
+    }
+
+    async fn set(&self, key: T, value: U, lifetime: Option<Duration>) {
+        let value = match serde_json::to_value(value) {
+            Ok(_value) => _value,
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                return
+            }
+        };
+        let expires_at = Utc::now() + lifetime.unwrap_or_else(get_default_lifetime);
+
+        let result = sqlx::query!(
+            r#"
+                INSERT INTO cache_entries (key, value, expires_at)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (key) DO UPDATE
+                SET value = EXCLUDED.value, expires_at = EXCLUDED.expires_at;
+            "#,
+            self.key(&key),
+            value,
+            expires_at
+        )
+        .execute(&self.pool)
+        .await;
+
+        if let Err(e) = result {
+            tracing::error!("{:?}", e);
+        }
+    }
+
+    async fn remove(&self, key: &T) -> Option<U> {
+        let result = sqlx::query!(
+            r#"
+                DELETE FROM cache_entries
+                WHERE key = $1
+                RETURNING value, expires_at > NOW() AS "alive!";
+            "#,
+            self.key(key)
+        )
+        .fetch_optional(&self.pool)
+        .await;
+
+        match result {
+            Ok(Some(record)) if record.alive => serde_json::from_value(record.value).ok(),
+            Ok(_) => None,
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                None
+            }
+        }
+    }
+}
+
+
+/// Periodically deletes expired records of every namespace
+pub fn spawn_purge_task(pool: AppPool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CACHE_PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let result = sqlx::query!("DELETE FROM cache_entries WHERE expires_at <= NOW()")
+                .execute(&pool)
+                .await;
+            match result {
+                Ok(_result) => tracing::debug!("Cache purge: {} expired record(s) deleted", _result.rows_affected()),
+                Err(e) => tracing::error!("{:?}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,233 @@
+use std::{fmt::Display, marker::PhantomData, time::Duration};
+
+use redis::{aio::{ConnectionManager, ConnectionManagerConfig}, Client};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    cache::cache::{get_default_lifetime, Cache},
+    settings::{CACHE_CONNECT_RETRIES, CACHE_CONNECT_TIMEOUT, CACHE_RECONNECT_MAX_DELAY}
+};
+
+
+/// Cache stored in any server speaking the RESP protocol (Redis, Valkey, KeyDB, ...).
+/// Records expire on the server side, values are stored as JSON.
+pub struct RespCache<T, U> {
+    namespace: &'static str,
+    connection: ConnectionManager,
+    _marker: PhantomData<fn() -> (T, U)>,
+}
+
+impl<T, U> RespCache<T, U> {
+    pub async fn connect(namespace: &'static str, url: &str) -> Result<Self, String> {
+        let connection = async {
+            let client = Client::open(url)?;
+            let config = ConnectionManagerConfig::new()
+                .set_number_of_retries(CACHE_CONNECT_RETRIES)
+                .set_max_delay(CACHE_RECONNECT_MAX_DELAY.as_millis() as u64)
+                .set_connection_timeout(CACHE_CONNECT_TIMEOUT);
+            ConnectionManager::new_with_config(client, config).await
+        }
+            .await
+            .map_err(|e| format!("Failed to connect to the cache at `{}`: {}", url, e))?;
+        Ok(Self {
+            namespace,
+            connection,
+            _marker: PhantomData,
+        })
+    }
+
+    fn key(&self, key: &impl Display) -> String {
+        format!("{}:{}", self.namespace, key)
+    }
+}
+
+
+impl<T, U> Cache<T, U> for RespCache<T, U>
+where
+    T: Display + Send + Sync,
+    U: Serialize + DeserializeOwned + Send + Sync,
+{
+    async fn get(&self, key: &T) -> Option<U> {
+        let result: redis::RedisResult<Option<String>> = redis::cmd("GET")
+            .arg(self.key(key))
+            .query_async(&mut self.connection.clone())
+            .await;
+
+        match result {
+            Ok(value) => value.and_then(|_value| serde_json::from_str(&_value).ok()),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                None
+            }
+        }
+    }
+
+    async fn set(&self, key: T, value: U, lifetime: Option<Duration>) {
+        let value = match serde_json::to_string(&value) {
+            Ok(_value) => _value,
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                return
+            }
+        };
+        let lifetime = lifetime.unwrap_or_else(get_default_lifetime);
+
+        let result: redis::RedisResult<()> = redis::cmd("SET")
+            .arg(self.key(&key))
+            .arg(value)
+            .arg("PX")
+            .arg(lifetime.as_millis() as u64)
+            .query_async(&mut self.connection.clone())
+            .await;
+
+        if let Err(e) = result {
+            tracing::error!("{:?}", e);
+        }
+    }
+
+    async fn remove(&self, key: &T) -> Option<U> {
+        let result: redis::RedisResult<Option<String>> = redis::cmd("GETDEL")
+            .arg(self.key(key))
+            .query_async(&mut self.connection.clone())
+            .await;
+
+        match result {
+            Ok(value) => value.and_then(|_value| serde_json::from_str(&_value).ok()),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                None
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+    use tokio::{io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader}, net::{TcpListener, TcpStream}};
+
+    use super::RespCache;
+    use crate::cache::cache::Cache;
+
+    type Store = Arc<Mutex<HashMap<String, (String, Option<Instant>)>>>;
+
+    /// Minimal RESP server understanding GET, SET with PX and GETDEL, anything else is acknowledged
+    async fn spawn_stub() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let store: Store = Arc::default();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(serve(socket, store.clone()));
+            }
+        });
+        format!("redis://{}", address)
+    }
+
+    async fn serve(socket: TcpStream, store: Store) {
+        let mut socket = BufReader::new(socket);
+        while let Some(command) = read_command(&mut socket).await {
+            let name = command[0].to_ascii_uppercase();
+            let reply = {
+                let mut store = store.lock().unwrap();
+                let now = Instant::now();
+                store.retain(|_, (_, deadline)| deadline.is_none_or(|deadline| deadline > now));
+                match name.as_str() {
+                    "GET" => bulk(store.get(&command[1]).map(|(value, _)| value.clone())),
+                    "GETDEL" => bulk(store.remove(&command[1]).map(|(value, _)| value)),
+                    "SET" => {
+                        let deadline = match command.get(3).map(|option| option.to_ascii_uppercase()) {
+                            Some(option) if option == "PX" => {
+                                Some(now + Duration::from_millis(command[4].parse().unwrap()))
+                            }
+                            _ => None,
+                        };
+                        store.insert(command[1].clone(), (command[2].clone(), deadline));
+                        "+OK\r\n".to_string()
+                    }
+                    _ => "+OK\r\n".to_string(),
+                }
+            };
+            if socket.get_mut().write_all(reply.as_bytes()).await.is_err() {
+                break
+            }
+        }
+    }
+
+    async fn read_command(socket: &mut BufReader<TcpStream>) -> Option<Vec<String>> {
+        let mut line = String::new();
+        socket.read_line(&mut line).await.ok().filter(|read| *read > 0)?;
+        let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+        let mut command = Vec::with_capacity(count);
+        for _ in 0..count {
+            line.clear();
+            socket.read_line(&mut line).await.ok()?;
+            let length: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+            let mut data = vec![0; length + 2];
+            socket.read_exact(&mut data).await.ok()?;
+            data.truncate(length);
+            command.push(String::from_utf8(data).ok()?);
+        }
+        Some(command)
+    }
+
+    fn bulk(value: Option<String>) -> String {
+        match value {
+            Some(value) => format!("${}\r\n{}\r\n", value.len(), value),
+            None => "$-1\r\n".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn set_get_and_remove() {
+        let url = spawn_stub().await;
+        let cache: RespCache<String, Vec<i64>> = RespCache::connect("test", &url).await.unwrap();
+
+        assert_eq!(cache.get(&"missing".to_string()).await, None);
+        cache.set("key".to_string(), vec![1, 2], None).await;
+        assert_eq!(cache.get(&"key".to_string()).await, Some(vec![1, 2]));
+
+        assert_eq!(cache.remove(&"key".to_string()).await, Some(vec![1, 2]));
+        assert_eq!(cache.remove(&"key".to_string()).await, None);
+        assert_eq!(cache.get(&"key".to_string()).await, None);
+    }
+
+    #[tokio::test]
+    async fn records_expire() {
+        let url = spawn_stub().await;
+        let cache: RespCache<String, String> = RespCache::connect("test", &url).await.unwrap();
+
+        cache.set("short".to_string(), "value".to_string(), Some(Duration::from_millis(50))).await;
+        cache.set("long".to_string(), "value".to_string(), Some(Duration::from_secs(60))).await;
+        assert_eq!(cache.get(&"short".to_string()).await, Some("value".to_string()));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(cache.get(&"short".to_string()).await, None);
+        assert_eq!(cache.remove(&"short".to_string()).await, None);
+        assert_eq!(cache.get(&"long".to_string()).await, Some("value".to_string()));
+    }
+
+    #[tokio::test]
+    async fn namespaces_do_not_collide() {
+        let url = spawn_stub().await;
+        let tickets: RespCache<String, String> = RespCache::connect("tickets", &url).await.unwrap();
+        let users: RespCache<String, String> = RespCache::connect("users", &url).await.unwrap();
+
+        tickets.set("key".to_string(), "ticket".to_string(), None).await;
+        assert_eq!(users.get(&"key".to_string()).await, None);
+        assert_eq!(tickets.get(&"key".to_string()).await, Some("ticket".to_string()));
+    }
+
+    #[tokio::test]
+    async fn connect_error_names_the_url() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let error = RespCache::<String, String>::connect("test", &url).await.err().unwrap();
+        assert!(error.contains(&url), "{}", error);
+    }
+}
@@ -0,0 +1,257 @@
+use std::sync::Arc;
+
+use axum::{extract::{Path, Query, State}, http::StatusCode, response::Response, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    access::blocks,
+    app_state::AppState,
+    auth::jwt_authorization::JWTAuthorize,
+    bus::bus::{ClusterEvent, EventBus},
+    models::{
+        chat::{Chat, ChatID, ChatUser, NewChat},
+        errors::ModelError,
+        message::{Message, MessageID},
+        user::UserID
+    },
+    settings::{CHAT_MAX_MEMBERS, MESSAGE_HISTORY_PAGE_SIZE}
+};
+
+
+#[derive(Debug, Deserialize)]
+pub struct NewChatForm {
+    pub name: String,
+    #[serde(default)]
+    pub encrypted: bool,
+    #[serde(default)]
+    pub member_ids: Vec<UserID>,
+    /// Chat with the only other user of `member_ids`, it can not get more members
+    #[serde(default)]
+    pub direct: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateChatForm {
+    pub members_can_pin: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    pub before: Option<MessageID>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ThreadQuery {
+    pub after: Option<MessageID>,
+    pub limit: Option<i64>,
+}
+
+/// Root message with a page of its replies
+#[derive(Debug, Serialize)]
+pub struct Thread {
+    pub root: Message,
+    pub replies: Vec<Message>,
+}
+
+
+pub async fn create_chat(
+    JWTAuthorize(jwt): JWTAuthorize,
+    State(state): State<Arc<AppState>>,
+    Json(form): Json<NewChatForm>,
+) -> Result<(StatusCode, Json<Chat>), Response> {
+    let name = form.name.trim();
+    if name.is_empty() {
+        let err = ModelError::ClientError("Chat name is empty".to_string());
+        return Err(ModelError::into_error_response(err, None, None))
+    }
+    let mut member_ids = form.member_ids;
+    member_ids.push(jwt.claims.user_id);
+    member_ids.sort_unstable();
+    member_ids.dedup();
+    if member_ids.len() > CHAT_MAX_MEMBERS {
+        let err = ModelError::ClientError(format!("A chat can not have more than {} members", CHAT_MAX_MEMBERS));
+        return Err(ModelError::into_error_response(err, None, None))
+    }
+    if form.direct && member_ids.len() != 2 {
+        let err = ModelError::ClientError("A direct chat is between you and one other user".to_string());
+        return Err(ModelError::into_error_response(err, None, None))
+    }
+    // Strangers can only be added if they let others find them
+    let unreachable_ids = ChatUser::unreachable_ids(jwt.claims.user_id, &member_ids, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if let Some(unreachable_id) = unreachable_ids.first() {
+        let err = ModelError::ClientError(format!("User {} can not be added, you do not share a chat", unreachable_id));
+        return Err(ModelError::into_error_response(err, Some(StatusCode::FORBIDDEN), None))
+    }
+    blocks::check_can_add(&state, jwt.claims.user_id, &member_ids)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, Some(StatusCode::FORBIDDEN), None))?;
+
+    // Opening a direct chat again returns the one the pair already has
+    if form.direct {
+        let existing = Chat::find_direct(member_ids[0], member_ids[1], &state.pool)
+            .await
+            .map_err(|err| ModelError::into_error_response(err, None, None))?;
+        if let Some(chat) = existing {
+            return Ok((StatusCode::OK, Json(chat)))
+        }
+    }
+
+    let chat = NewChat {
+        id: state.snowflake_generator.generate_id().await,
+        owner_id: jwt.claims.user_id,
+        name: name.to_string(),
+        encrypted: form.encrypted,
+        is_direct: form.direct,
+    }
+        .insert(&member_ids, &state.pool)
+        .await;
+
+    let chat = match chat {
+        Ok(chat) => chat,
+        // Another request created the direct chat of the pair in the meantime
+        Err(err) if form.direct => match Chat::find_direct(member_ids[0], member_ids[1], &state.pool).await {
+            Ok(Some(chat)) => return Ok((StatusCode::OK, Json(chat))),
+            _ => return Err(ModelError::into_error_response(err, None, None)),
+        },
+        Err(err) => return Err(ModelError::into_error_response(err, None, None)),
+    };
+
+    Ok((StatusCode::CREATED, Json(chat)))
+}
+
+
+/// Changes settings of the chat, only for its owners
+pub async fn update_chat(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path(chat_id): Path<ChatID>,
+    State(state): State<Arc<AppState>>,
+    Json(form): Json<UpdateChatForm>,
+) -> Result<Json<Chat>, Response> {
+    let is_owner = ChatUser::is_owner(chat_id, jwt.claims.user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !is_owner {
+        let err = ModelError::ClientError("Only owners can change the chat".to_string());
+        return Err(ModelError::into_error_response(err, Some(StatusCode::FORBIDDEN), None))
+    }
+
+    let chat = match form.members_can_pin {
+        Some(members_can_pin) => Chat::update_members_can_pin(chat_id, members_can_pin, &state.pool).await,
+        None => Chat::find(chat_id, &state.pool).await,
+    }
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .ok_or_else(|| {
+            let err = ModelError::ClientError("Chat not found".to_string());
+            ModelError::into_error_response(err, Some(StatusCode::NOT_FOUND), None)
+        })?;
+
+    if form.members_can_pin.is_some() {
+        state.bus.publish(ClusterEvent::ChatUpdated { chat_id }).await;
+    }
+    Ok(Json(chat))
+}
+
+
+/// Messages of the chat, newest first. Encrypted chats return payloads instead of content
+pub async fn history(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path(chat_id): Path<ChatID>,
+    Query(query): Query<HistoryQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<Message>>, Response> {
+    let is_member = ChatUser::is_member(chat_id, jwt.claims.user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !is_member {
+        let err = ModelError::ClientError("You are not a member of this chat".to_string());
+        return Err(ModelError::into_error_response(err, Some(StatusCode::FORBIDDEN), None))
+    }
+
+    let limit = query.limit.unwrap_or(MESSAGE_HISTORY_PAGE_SIZE).clamp(1, MESSAGE_HISTORY_PAGE_SIZE);
+    let messages = Message::list(chat_id, jwt.claims.user_id, query.before, limit, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    Ok(Json(messages))
+}
+
+
+/// Messages mentioning the user across the user's chats, newest first
+pub async fn mentions(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Query(query): Query<HistoryQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<Message>>, Response> {
+    let limit = query.limit.unwrap_or(MESSAGE_HISTORY_PAGE_SIZE).clamp(1, MESSAGE_HISTORY_PAGE_SIZE);
+    let messages = Message::mentioning(jwt.claims.user_id, query.before, limit, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    Ok(Json(messages))
+}
+
+
+pub async fn mute_chat(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path(chat_id): Path<ChatID>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response> {
+    set_muted(&state, jwt.claims.user_id, chat_id, true).await
+}
+
+
+pub async fn unmute_chat(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path(chat_id): Path<ChatID>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response> {
+    set_muted(&state, jwt.claims.user_id, chat_id, false).await
+}
+
+async fn set_muted(state: &Arc<AppState>, user_id: UserID, chat_id: ChatID, muted: bool) -> Result<StatusCode, Response> {
+    let updated = ChatUser::set_muted(chat_id, user_id, muted, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !updated {
+        let err = ModelError::ClientError("You are not a member of this chat".to_string());
+        return Err(ModelError::into_error_response(err, Some(StatusCode::FORBIDDEN), None))
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+/// Replies to the root message, oldest first, `reply_count` of the root is the size of the thread
+pub async fn thread(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path((chat_id, message_id)): Path<(ChatID, MessageID)>,
+    Query(query): Query<ThreadQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Thread>, Response> {
+    let is_member = ChatUser::is_member(chat_id, jwt.claims.user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !is_member {
+        let err = ModelError::ClientError("You are not a member of this chat".to_string());
+        return Err(ModelError::into_error_response(err, Some(StatusCode::FORBIDDEN), None))
+    }
+
+    let root = Message::find(message_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .filter(|root| root.chat_id == chat_id)
+        .ok_or_else(|| {
+            let err = ModelError::ClientError("Message not found".to_string());
+            ModelError::into_error_response(err, Some(StatusCode::NOT_FOUND), None)
+        })?;
+    if root.thread_root_id.is_some() {
+        let err = ModelError::ClientError("The message is a reply, its thread starts at `thread_root_id`".to_string());
+        return Err(ModelError::into_error_response(err, None, None))
+    }
+
+    let limit = query.limit.unwrap_or(MESSAGE_HISTORY_PAGE_SIZE).clamp(1, MESSAGE_HISTORY_PAGE_SIZE);
+    let replies = Message::thread(message_id, jwt.claims.user_id, query.after, limit, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    Ok(Json(Thread { root, replies }))
+}
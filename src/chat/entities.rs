@@ -0,0 +1,122 @@
+use std::cmp::Reverse;
+
+use crate::{
+    models::{errors::ModelError, message::{EntityKind, MessageEntity}},
+    settings::{ENTITY_LANGUAGE_MAX_LENGTH, ENTITY_URL_MAX_LENGTH, MESSAGE_MAX_ENTITIES}
+};
+
+
+/// Checks the entities against `content` as it was sent and moves them onto the trimmed content.
+/// Parts of entities covering the trimmed whitespace are cut off, entities covering only whitespace are dropped.
+/// Entities may be nested but not overlap partially.
+pub fn validate_entities(content: &str, entities: Vec<MessageEntity>) -> Result<Vec<MessageEntity>, ModelError> {
+    if entities.len() > MESSAGE_MAX_ENTITIES {
+        return Err(ModelError::ClientError(format!("A message can not have more than {} entities", MESSAGE_MAX_ENTITIES)))
+    }
+
+    // Offsets in UTF-16 code units where a character starts, an entity can not split a surrogate pair
+    let mut boundaries = vec![true];
+    for c in content.chars() {
+        if c.len_utf16() == 2 {
+            boundaries.push(false);
+        }
+        boundaries.push(true);
+    }
+    let total = boundaries.len() - 1;
+    let lead = content[..content.len() - content.trim_start().len()].encode_utf16().count();
+    let trimmed_end = lead + content.trim().encode_utf16().count();
+
+    let mut validated = Vec::with_capacity(entities.len());
+    for entity in entities {
+        let end = entity.offset.checked_add(entity.length)
+            .filter(|end| entity.length > 0 && *end <= total)
+            .ok_or_else(|| ModelError::ClientError(format!("Entity at {} is out of the text", entity.offset)))?;
+        if !boundaries[entity.offset] || !boundaries[end] {
+            return Err(ModelError::ClientError(format!("Entity at {} splits a character", entity.offset)))
+        }
+        let kind = validate_kind(entity.kind)?;
+
+        let start = entity.offset.max(lead);
+        let end = end.min(trimmed_end);
+        if start < end {
+            validated.push(MessageEntity { offset: start - lead, length: end - start, kind });
+        }
+    }
+    // Enclosing entities go first, so every client nests them the same way
+    validated.sort_by_key(|entity| (entity.offset, Reverse(entity.length)));
+
+    // Ends of the entities enclosing the current one, innermost last
+    let mut enclosing_ends: Vec<usize> = Vec::new();
+    for entity in &validated {
+        let end = entity.offset + entity.length;
+        while enclosing_ends.last().is_some_and(|enclosing_end| *enclosing_end <= entity.offset) {
+            enclosing_ends.pop();
+        }
+        if enclosing_ends.last().is_some_and(|enclosing_end| end > *enclosing_end) {
+            return Err(ModelError::ClientError("Entities can be nested but must not overlap partially".to_string()))
+        }
+        enclosing_ends.push(end);
+    }
+    Ok(validated)
+}
+
+fn validate_kind(kind: EntityKind) -> Result<EntityKind, ModelError> {
+    match kind {
+        EntityKind::Pre { language } => {
+            let language = language
+                .map(|language| language.trim().to_string())
+                .filter(|language| !language.is_empty());
+            if let Some(language) = &language {
+                let valid = language.chars().count() <= ENTITY_LANGUAGE_MAX_LENGTH
+                    && language.chars().all(|c| c.is_ascii_alphanumeric() || "+-#_.".contains(c));
+                if !valid {
+                    return Err(ModelError::ClientError(format!("Invalid code language `{}`", language)))
+                }
+            }
+            Ok(EntityKind::Pre { language })
+        }
+        EntityKind::Link { url } => {
+            let url = url.trim().to_string();
+            let valid = url.len() <= ENTITY_URL_MAX_LENGTH
+                && ["http://", "https://", "mailto:"].iter().any(|scheme| url.starts_with(scheme))
+                && !url.chars().any(|c| c.is_whitespace() || c.is_control());
+            if !valid {
+                return Err(ModelError::ClientError("Links must be http, https or mailto URLs".to_string()))
+            }
+            Ok(EntityKind::Link { url })
+        }
+        kind => Ok(kind),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::validate_entities;
+    use crate::models::message::{EntityKind, MessageEntity};
+
+    fn entity(offset: usize, length: usize) -> MessageEntity {
+        MessageEntity { offset, length, kind: EntityKind::Bold }
+    }
+
+    #[test]
+    fn accepts_nested_entities() {
+        let entities = vec![entity(2, 3), entity(0, 10), entity(2, 3), entity(6, 4)];
+        let Ok(validated) = validate_entities("0123456789", entities) else {
+            panic!("Nested entities are rejected")
+        };
+        let ranges: Vec<_> = validated.iter().map(|entity| (entity.offset, entity.length)).collect();
+        assert_eq!(ranges, vec![(0, 10), (2, 3), (2, 3), (6, 4)]);
+    }
+
+    #[test]
+    fn rejects_partially_overlapping_entities() {
+        assert!(validate_entities("0123456789", vec![entity(0, 5), entity(3, 5)]).is_err());
+        assert!(validate_entities("0123456789", vec![entity(0, 10), entity(1, 3), entity(2, 4)]).is_err());
+    }
+
+    #[test]
+    fn accepts_adjacent_entities() {
+        assert!(validate_entities("0123456789", vec![entity(0, 5), entity(5, 5)]).is_ok());
+    }
+}
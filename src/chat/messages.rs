@@ -0,0 +1,280 @@
+use std::{collections::{hash_map::Entry, HashMap, HashSet}, sync::Arc};
+
+use serde::Deserialize;
+
+use crate::{
+    access::blocks,
+    app_state::AppState,
+    bus::bus::{ClusterEvent, EventBus},
+    chat::entities::validate_entities,
+    crypto::mls::inspect_protocol_message,
+    models::{
+        attachment::{Attachment, AttachmentID},
+        chat::{Chat, ChatID, ChatUser},
+        errors::ModelError,
+        message::{EntityKind, ForwardOrigin, Message, MessageEntity, MessageID, NewEncryptedMessage, NewMessage, Quote},
+        mls::MlsGroup,
+        user::{User, UserID}
+    },
+    settings::{MESSAGE_MAX_ATTACHMENTS, MESSAGE_MAX_FORWARD, MESSAGE_MAX_MENTIONS, MESSAGE_QUOTE_MAX_LENGTH}
+};
+
+
+/// Plaintext message as it is sent by a client
+#[derive(Debug, Deserialize)]
+pub struct MessageDraft {
+    #[serde(default)]
+    pub content: String,
+    /// Completed uploads of the sender to the same chat
+    #[serde(default)]
+    pub attachments: Vec<AttachmentID>,
+    /// Message of the same chat the message replies to
+    pub reply_to: Option<MessageID>,
+    /// Members mentioned without `@username` in the content
+    #[serde(default)]
+    pub mentions: Vec<UserID>,
+    /// Formatting of `content` as it is sent, before trimming
+    #[serde(default)]
+    pub entities: Vec<MessageEntity>,
+}
+
+
+/// Usernames mentioned as `@username`, the `@` must not follow a word character
+fn parse_mentions(content: &str) -> Vec<String> {
+    let is_username_char = |c: char| c.is_alphanumeric() || c == '_';
+    let mut usernames = Vec::new();
+    let mut previous = None;
+    for (index, c) in content.char_indices() {
+        if c == '@' && !previous.is_some_and(is_username_char) {
+            let rest = &content[index + 1..];
+            let end = rest.find(|c: char| !is_username_char(c)).unwrap_or(rest.len());
+            if end > 0 && !usernames.iter().any(|username| username == &rest[..end]) {
+                usernames.push(rest[..end].to_string());
+            }
+        }
+        previous = Some(c);
+    }
+    usernames
+}
+
+
+async fn find_member_chat(state: &Arc<AppState>, user_id: UserID, chat_id: ChatID) -> Result<Chat, ModelError> {
+    if !ChatUser::is_member(chat_id, user_id, &state.pool).await? {
+        return Err(ModelError::ClientError("You are not a member of this chat".to_string()))
+    }
+    Chat::find(chat_id, &state.pool)
+        .await?
+        .ok_or_else(|| ModelError::ClientError("Chat not found".to_string()))
+}
+
+
+/// Stores a plaintext message and fans it out to the chat members
+pub async fn send_message(state: &Arc<AppState>, user_id: UserID, chat_id: ChatID, draft: MessageDraft) -> Result<Message, ModelError> {
+    let content = draft.content.trim();
+    let mut entities = validate_entities(&draft.content, draft.entities)?;
+    let mut attachment_ids = draft.attachments;
+    if content.is_empty() && attachment_ids.is_empty() {
+        return Err(ModelError::ClientError("Message is empty".to_string()))
+    }
+    let mut seen = HashSet::new();
+    attachment_ids.retain(|attachment_id| seen.insert(*attachment_id));
+    if attachment_ids.len() > MESSAGE_MAX_ATTACHMENTS {
+        return Err(ModelError::ClientError(format!("A message can not have more than {} attachments", MESSAGE_MAX_ATTACHMENTS)))
+    }
+    let chat = find_member_chat(state, user_id, chat_id).await?;
+    if chat.encrypted {
+        return Err(ModelError::ClientError("The chat is encrypted, only encrypted messages are accepted".to_string()))
+    }
+    blocks::check_can_post(state, user_id, &chat).await?;
+    if !attachment_ids.is_empty() {
+        let attachable = Attachment::count_attachable(&attachment_ids, user_id, chat_id, &state.pool).await?;
+        if attachable as usize != attachment_ids.len() {
+            return Err(ModelError::ClientError("Attachments must be completed uploads of yours to this chat".to_string()))
+        }
+    }
+
+    let usernames = parse_mentions(content);
+    let mut explicit_mentions = draft.mentions;
+    explicit_mentions.extend(entities.iter().filter_map(|entity| match entity.kind {
+        EntityKind::Mention { user_id } => Some(user_id),
+        _ => None,
+    }));
+    let mut seen = HashSet::new();
+    explicit_mentions.retain(|mentioned_id| seen.insert(*mentioned_id));
+    if usernames.len() + explicit_mentions.len() > MESSAGE_MAX_MENTIONS {
+        return Err(ModelError::ClientError(format!("A message can not mention more than {} users", MESSAGE_MAX_MENTIONS)))
+    }
+    // Mentions of users outside the chat are kept as plain text
+    let mention_ids = if usernames.is_empty() && explicit_mentions.is_empty() {
+        Vec::new()
+    } else {
+        ChatUser::resolve_mentions(chat_id, user_id, &usernames, &explicit_mentions, &state.pool).await?
+    };
+    entities.retain(|entity| match entity.kind {
+        EntityKind::Mention { user_id: mentioned_id } => mentioned_id == user_id || mention_ids.contains(&mentioned_id),
+        _ => true,
+    });
+    // The mention stays in the text, but users who have blocked the sender are not notified
+    let mention_ids = blocks::notified_mentions(state, user_id, mention_ids).await?;
+
+    let (thread_root_id, quote) = match draft.reply_to {
+        Some(reply_to) => {
+            let replied = Message::find(reply_to, &state.pool)
+                .await?
+                .filter(|replied| replied.chat_id == chat_id)
+                .ok_or_else(|| ModelError::ClientError("The replied message is not in this chat".to_string()))?;
+            let quote = Quote {
+                message_id: replied.id,
+                user_id: replied.user_id,
+                content: replied.content.map(|content| content.chars().take(MESSAGE_QUOTE_MAX_LENGTH).collect()),
+            };
+            // Replies to a reply stay in the thread of the first message
+            (Some(replied.thread_root_id.unwrap_or(replied.id)), Some(quote))
+        }
+        None => (None, None),
+    };
+
+    let message = NewMessage {
+        id: state.snowflake_generator.generate_id().await,
+        user_id,
+        chat_id,
+        content: content.to_string(),
+        attachment_ids,
+        reply_to: draft.reply_to,
+        thread_root_id,
+        quote,
+        forwarded_from: None,
+        mention_ids,
+        entities,
+    }
+        .insert(&state.pool)
+        .await?;
+
+    // The sender receives the message through the bus, like every other member
+    state.bus.publish(ClusterEvent::MessageCreated { chat_id, message_id: message.id }).await;
+    Ok(message)
+}
+
+
+/// Copies messages of `from_chat_id` into `chat_id` in their original order.
+/// Attachments are shared with the copies, a forwarded message keeps its first origin.
+pub async fn forward_messages(state: &Arc<AppState>, user_id: UserID, from_chat_id: ChatID, chat_id: ChatID, mut message_ids: Vec<MessageID>) -> Result<Vec<Message>, ModelError> {
+    let mut seen = HashSet::new();
+    message_ids.retain(|message_id| seen.insert(*message_id));
+    if message_ids.is_empty() || message_ids.len() > MESSAGE_MAX_FORWARD {
+        return Err(ModelError::ClientError(format!("From 1 to {} messages can be forwarded at once", MESSAGE_MAX_FORWARD)))
+    }
+    let source = find_member_chat(state, user_id, from_chat_id).await?;
+    let target = find_member_chat(state, user_id, chat_id).await?;
+    if source.encrypted || target.encrypted {
+        return Err(ModelError::ClientError("Messages of encrypted chats can only be forwarded by the clients".to_string()))
+    }
+    blocks::check_can_post(state, user_id, &target).await?;
+
+    let mut originals = Vec::with_capacity(message_ids.len());
+    for message_id in message_ids {
+        let original = Message::find(message_id, &state.pool)
+            .await?
+            .filter(|original| original.chat_id == from_chat_id)
+            .ok_or_else(|| ModelError::ClientError(format!("Message {} is not in the chat", message_id)))?;
+        originals.push(original);
+    }
+    originals.sort_by_key(|original| original.id);
+    // Mentions of users outside the target chat are kept as plain text, as in `send_message`
+    let target_member_ids: HashSet<UserID> = ChatUser::member_ids(chat_id, &state.pool)
+        .await?
+        .into_iter()
+        .collect();
+
+    let mut authors: HashMap<UserID, User> = HashMap::new();
+    let mut batch = Vec::with_capacity(originals.len());
+    for original in originals {
+        let mut entities = original.entities.0;
+        entities.retain(|entity| match entity.kind {
+            EntityKind::Mention { user_id: mentioned_id } => target_member_ids.contains(&mentioned_id),
+            _ => true,
+        });
+        let origin = match original.forwarded_from {
+            Some(origin) => origin.0,
+            None => {
+                let author = match authors.entry(original.user_id) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(
+                        User::find(original.user_id, &state.pool)
+                            .await?
+                            .ok_or_else(|| ModelError::UnexpectedError("Internal server error".to_string()))?
+                    ),
+                };
+                let linked = author.link_forwards;
+                ForwardOrigin {
+                    user_id: linked.then_some(original.user_id),
+                    chat_id: linked.then_some(from_chat_id),
+                    message_id: linked.then_some(original.id),
+                    name: author.name.clone(),
+                }
+            }
+        };
+
+        batch.push(NewMessage {
+            id: state.snowflake_generator.generate_id().await,
+            user_id,
+            chat_id,
+            content: original.content.unwrap_or_default(),
+            attachment_ids: original.attachments.0.iter().map(|attachment| attachment.id).collect(),
+            reply_to: None,
+            thread_root_id: None,
+            quote: None,
+            forwarded_from: Some(origin),
+            // Forwarding does not notify the users mentioned in the original
+            mention_ids: Vec::new(),
+            entities,
+        });
+    }
+
+    // Either the whole batch is forwarded or nothing, members are notified once it is stored
+    let forwarded = NewMessage::insert_batch(&batch, &state.pool).await?;
+    for message in &forwarded {
+        state.bus.publish(ClusterEvent::MessageCreated { chat_id, message_id: message.id }).await;
+    }
+    Ok(forwarded)
+}
+
+
+/// Stores an MLS `PrivateMessage` with application content of the chat's group.
+/// The server only checks the framing, the content stays encrypted.
+pub async fn send_encrypted_message(state: &Arc<AppState>, user_id: UserID, chat_id: ChatID, payload: Vec<u8>, sender_leaf: u32) -> Result<Message, ModelError> {
+    let chat = find_member_chat(state, user_id, chat_id).await?;
+    if !chat.encrypted {
+        return Err(ModelError::ClientError("The chat is not encrypted".to_string()))
+    }
+    blocks::check_can_post(state, user_id, &chat).await?;
+    let group = MlsGroup::find_by_chat(chat_id, &state.pool)
+        .await?
+        .ok_or_else(|| ModelError::ClientError("The chat has no MLS group".to_string()))?;
+
+    let info = inspect_protocol_message(&payload)
+        .map_err(ModelError::ClientError)?;
+    if info.is_public || info.content_type != openmls::prelude::ContentType::Application {
+        return Err(ModelError::ClientError("Only a PrivateMessage with application content can be sent".to_string()))
+    }
+    if info.group_id != group.group_id {
+        return Err(ModelError::ClientError("The message belongs to another MLS group".to_string()))
+    }
+    if info.epoch as i64 > group.epoch {
+        return Err(ModelError::ClientError(format!("Epoch {} is ahead of the group epoch {}", info.epoch, group.epoch)))
+    }
+
+    let message = NewEncryptedMessage {
+        id: state.snowflake_generator.generate_id().await,
+        user_id,
+        chat_id,
+        payload,
+        epoch: info.epoch as i64,
+        sender_leaf: sender_leaf as i32,
+    }
+        .insert(&state.pool)
+        .await?;
+
+    state.bus.publish(ClusterEvent::MessageCreated { chat_id, message_id: message.id }).await;
+    Ok(message)
+}
@@ -0,0 +1,6 @@
+pub mod chat;
+pub mod entities;
+pub mod messages;
+pub mod pins;
+pub mod reactions;
+pub mod search;
@@ -0,0 +1,140 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{extract::{Path, State}, http::StatusCode, response::Response, Json};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    access::blocks,
+    app_state::AppState,
+    auth::jwt_authorization::JWTAuthorize,
+    bus::bus::{ClusterEvent, EventBus},
+    models::{
+        chat::{Chat, ChatID, ChatUser},
+        errors::ModelError,
+        message::{Message, MessageID},
+        pin::Pin,
+        user::UserID
+    },
+    settings::CHAT_MAX_PINS
+};
+
+
+#[derive(Debug, Serialize)]
+pub struct PinnedMessage {
+    pub pinned_by: UserID,
+    pub pinned_at: DateTime<Utc>,
+    pub message: Message,
+}
+
+
+async fn check_member(state: &Arc<AppState>, user_id: UserID, chat_id: ChatID) -> Result<(), Response> {
+    let is_member = ChatUser::is_member(chat_id, user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !is_member {
+        let err = ModelError::ClientError("You are not a member of this chat".to_string());
+        return Err(ModelError::into_error_response(err, Some(StatusCode::FORBIDDEN), None))
+    }
+    Ok(())
+}
+
+/// Owners can always pin, other members only if the chat allows it. Nobody blocked by the peer of a direct chat can pin
+async fn check_can_pin(state: &Arc<AppState>, user_id: UserID, chat_id: ChatID) -> Result<(), Response> {
+    check_member(state, user_id, chat_id).await?;
+    let chat = Chat::find(chat_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .ok_or_else(|| {
+            let err = ModelError::ClientError("Chat not found".to_string());
+            ModelError::into_error_response(err, Some(StatusCode::NOT_FOUND), None)
+        })?;
+    blocks::check_can_post(state, user_id, &chat)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, Some(StatusCode::FORBIDDEN), None))?;
+    if chat.members_can_pin {
+        return Ok(())
+    }
+    let is_owner = ChatUser::is_owner(chat_id, user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !is_owner {
+        let err = ModelError::ClientError("Only owners can pin messages in this chat".to_string());
+        return Err(ModelError::into_error_response(err, Some(StatusCode::FORBIDDEN), None))
+    }
+    Ok(())
+}
+
+
+pub async fn list_pins(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path(chat_id): Path<ChatID>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<PinnedMessage>>, Response> {
+    check_member(&state, jwt.claims.user_id, chat_id).await?;
+    let pins = Pin::list(chat_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+
+    let message_ids: Vec<MessageID> = pins.iter().map(|pin| pin.message_id).collect();
+    let mut messages: HashMap<MessageID, Message> = Message::find_many(&message_ids, jwt.claims.user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .into_iter()
+        .map(|message| (message.id, message))
+        .collect();
+
+    let pinned = pins.into_iter()
+        .filter_map(|pin| {
+            let message = messages.remove(&pin.message_id)?;
+            Some(PinnedMessage { pinned_by: pin.pinned_by, pinned_at: pin.pinned_at, message })
+        })
+        .collect();
+    Ok(Json(pinned))
+}
+
+
+pub async fn pin_message(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path((chat_id, message_id)): Path<(ChatID, MessageID)>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response> {
+    let user_id = jwt.claims.user_id;
+    check_can_pin(&state, user_id, chat_id).await?;
+    let in_chat = Message::find(message_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .is_some_and(|message| message.chat_id == chat_id);
+    if !in_chat {
+        let err = ModelError::ClientError("Message not found".to_string());
+        return Err(ModelError::into_error_response(err, Some(StatusCode::NOT_FOUND), None))
+    }
+
+    let pinned = Pin::insert(chat_id, message_id, user_id, CHAT_MAX_PINS, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, Some(StatusCode::CONFLICT), None))?;
+    if pinned {
+        state.bus.publish(ClusterEvent::PinChanged { chat_id, message_id, user_id, pinned: true }).await;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+pub async fn unpin_message(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path((chat_id, message_id)): Path<(ChatID, MessageID)>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response> {
+    let user_id = jwt.claims.user_id;
+    check_can_pin(&state, user_id, chat_id).await?;
+    let unpinned = Pin::delete(chat_id, message_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !unpinned {
+        let err = ModelError::ClientError("The message is not pinned".to_string());
+        return Err(ModelError::into_error_response(err, Some(StatusCode::NOT_FOUND), None))
+    }
+
+    state.bus.publish(ClusterEvent::PinChanged { chat_id, message_id, user_id, pinned: false }).await;
+    Ok(StatusCode::NO_CONTENT)
+}
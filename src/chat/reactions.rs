@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use crate::{
+    access::blocks,
+    app_state::AppState,
+    bus::bus::{ClusterEvent, EventBus},
+    models::{
+        chat::{Chat, ChatUser},
+        errors::ModelError,
+        message::{Message, MessageID},
+        reaction::Reaction,
+        user::UserID
+    },
+    settings::{MESSAGE_MAX_DISTINCT_REACTIONS, REACTION_MAX_LENGTH}
+};
+
+
+/// Code points which are emoji on their own, the ranges of the Unicode Extended_Pictographic property
+fn is_pictographic(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x00a9 | 0x00ae | 0x203c | 0x2049 | 0x2122 | 0x2139 | 0x2194..=0x2199 | 0x21a9..=0x21aa
+            | 0x231a..=0x231b | 0x2328 | 0x2388 | 0x23cf | 0x23e9..=0x23f3 | 0x23f8..=0x23fa | 0x24c2
+            | 0x25aa..=0x25ab | 0x25b6 | 0x25c0 | 0x25fb..=0x25fe | 0x2600..=0x27bf | 0x2934..=0x2935
+            | 0x2b05..=0x2b07 | 0x2b1b..=0x2b1c | 0x2b50 | 0x2b55 | 0x3030 | 0x303d | 0x3297 | 0x3299
+            | 0x1f000..=0x1f1e5 | 0x1f200..=0x1faff
+    )
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1f1e6}'..='\u{1f1ff}').contains(&c)
+}
+
+/// Skin tones
+fn is_modifier(c: char) -> bool {
+    ('\u{1f3fb}'..='\u{1f3ff}').contains(&c)
+}
+
+/// Whether the text is exactly one emoji: a flag, a keycap, a tag sequence
+/// or emoji joined with ZWJ, each optionally followed by a presentation selector or a skin tone
+fn is_single_emoji(emoji: &str) -> bool {
+    let chars: Vec<char> = emoji.chars().collect();
+    match chars.as_slice() {
+        [first, second] if is_regional_indicator(*first) && is_regional_indicator(*second) => return true,
+        [key, '\u{fe0f}', '\u{20e3}'] | [key, '\u{20e3}'] if key.is_ascii_digit() || *key == '#' || *key == '*' => return true,
+        ['\u{1f3f4}', tags @ .., '\u{e007f}'] if !tags.is_empty() => {
+            return tags.iter().all(|tag| ('\u{e0020}'..='\u{e007e}').contains(tag))
+        }
+        _ => {}
+    }
+
+    emoji.split('\u{200d}').all(|element| {
+        let mut chars = element.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some(base), None, None) => is_pictographic(base),
+            (Some(base), Some(suffix), None) => is_pictographic(base) && (suffix == '\u{fe0f}' || is_modifier(suffix)),
+            _ => false,
+        }
+    })
+}
+
+fn validate_emoji(emoji: &str) -> Result<(), ModelError> {
+    if emoji.chars().count() > REACTION_MAX_LENGTH || !is_single_emoji(emoji) {
+        return Err(ModelError::ClientError("Invalid emoji".to_string()))
+    }
+    Ok(())
+}
+
+/// Finds the message if the user is a member of its chat
+async fn find_member_message(state: &Arc<AppState>, user_id: UserID, message_id: MessageID) -> Result<Message, ModelError> {
+    let message = Message::find(message_id, &state.pool)
+        .await?
+        .ok_or_else(|| ModelError::ClientError("Message not found".to_string()))?;
+    if !ChatUser::is_member(message.chat_id, user_id, &state.pool).await? {
+        return Err(ModelError::ClientError("Message not found".to_string()))
+    }
+    Ok(message)
+}
+
+
+pub async fn add_reaction(state: &Arc<AppState>, user_id: UserID, message_id: MessageID, emoji: String) -> Result<(), ModelError> {
+    let emoji = emoji.trim().to_string();
+    validate_emoji(&emoji)?;
+    let message = find_member_message(state, user_id, message_id).await?;
+    let chat = Chat::find(message.chat_id, &state.pool)
+        .await?
+        .ok_or_else(|| ModelError::ClientError("Message not found".to_string()))?;
+    // Members of an encrypted chat react with encrypted messages, the server must not see them
+    if chat.encrypted {
+        return Err(ModelError::ClientError("Reactions in an encrypted chat must be sent encrypted".to_string()))
+    }
+    blocks::check_can_post(state, user_id, &chat).await?;
+
+    let reaction = Reaction { message_id, user_id, emoji };
+    if reaction.insert(MESSAGE_MAX_DISTINCT_REACTIONS, &state.pool).await? {
+        state.bus.publish(ClusterEvent::ReactionChanged {
+            chat_id: message.chat_id,
+            message_id,
+            user_id,
+            emoji: reaction.emoji,
+            added: true,
+        }).await;
+    }
+    Ok(())
+}
+
+
+pub async fn remove_reaction(state: &Arc<AppState>, user_id: UserID, message_id: MessageID, emoji: String) -> Result<(), ModelError> {
+    let message = find_member_message(state, user_id, message_id).await?;
+    let reaction = Reaction { message_id, user_id, emoji: emoji.trim().to_string() };
+    if reaction.delete(&state.pool).await? {
+        state.bus.publish(ClusterEvent::ReactionChanged {
+            chat_id: message.chat_id,
+            message_id,
+            user_id,
+            emoji: reaction.emoji,
+            added: false,
+        }).await;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::is_single_emoji;
+
+    #[test]
+    fn accepts_single_emoji() {
+        for emoji in ["👍", "❤️", "🇺🇦", "1️⃣", "#⃣", "👍🏽", "👨‍👩‍👧‍👦", "🏳️‍🌈", "🏃🏻‍♀️", "🏴\u{e0067}\u{e0062}\u{e0065}\u{e006e}\u{e0067}\u{e007f}"] {
+            assert!(is_single_emoji(emoji), "{}", emoji);
+        }
+    }
+
+    #[test]
+    fn rejects_text_and_several_emoji() {
+        for emoji in ["", "a", "1", "é", "字", "👍👍", "👍 ", "🇺", "🇺🇦🇺", "\u{200d}👍", "👍\u{200d}", "👍a", "\u{fe0f}", "🏴\u{e007f}"] {
+            assert!(!is_single_emoji(emoji), "{:?}", emoji);
+        }
+    }
+}
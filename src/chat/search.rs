@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use axum::{extract::{Path, Query, State}, http::StatusCode, response::Response, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    auth::jwt_authorization::JWTAuthorize,
+    models::{
+        chat::{ChatID, ChatUser},
+        errors::ModelError,
+        message::{MessageID, MessageSearch, MessageSearchHit},
+        user::UserID
+    },
+    settings::{MESSAGE_SEARCH_MAX_QUERY_LENGTH, MESSAGE_SEARCH_PAGE_SIZE}
+};
+
+
+/// `q` supports quoted phrases, `or` and `-word`, `since` and `until` are RFC 3339 times
+#[derive(Debug, Deserialize)]
+pub struct MessageSearchQuery {
+    pub q: String,
+    /// Only messages of this author
+    pub from: Option<UserID>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// `next_before` of the previous page
+    pub before: Option<MessageID>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessageSearchPage {
+    pub hits: Vec<MessageSearchHit>,
+    /// Cursor of the next page, absent on the last page
+    pub next_before: Option<MessageID>,
+}
+
+
+/// Smallest snowflake generated at `time`, times past the last representable one are clamped to it
+fn snowflake_at(time: DateTime<Utc>) -> MessageID {
+    time.timestamp_millis().clamp(0, MessageID::MAX >> 22) << 22
+}
+
+async fn search(state: &Arc<AppState>, searcher_id: UserID, chat_id: Option<ChatID>, query: MessageSearchQuery) -> Result<MessageSearchPage, ModelError> {
+    let q = query.q.trim();
+    if q.is_empty() || q.chars().count() > MESSAGE_SEARCH_MAX_QUERY_LENGTH {
+        return Err(ModelError::ClientError(format!("Query must contain from 1 to {} characters", MESSAGE_SEARCH_MAX_QUERY_LENGTH)))
+    }
+    let before_id = match (query.before, query.until.map(snowflake_at)) {
+        (Some(before), Some(until)) => Some(before.min(until)),
+        (before, until) => before.or(until),
+    };
+
+    let limit = query.limit.unwrap_or(MESSAGE_SEARCH_PAGE_SIZE).clamp(1, MESSAGE_SEARCH_PAGE_SIZE);
+    let hits = MessageSearch {
+        query: q.to_string(),
+        searcher_id,
+        chat_id,
+        author_id: query.from,
+        min_id: query.since.map(snowflake_at),
+        before_id,
+        limit,
+    }
+        .run(&state.pool)
+        .await?;
+
+    let next_before = if hits.len() as i64 == limit { hits.last().map(|hit| hit.id) } else { None };
+    Ok(MessageSearchPage { hits, next_before })
+}
+
+
+/// Searches all plaintext chats of the user
+pub async fn search_messages(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Query(query): Query<MessageSearchQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<MessageSearchPage>, Response> {
+    let page = search(&state, jwt.claims.user_id, None, query)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    Ok(Json(page))
+}
+
+
+/// Searches a single chat, an encrypted chat has no results
+pub async fn search_chat_messages(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path(chat_id): Path<ChatID>,
+    Query(query): Query<MessageSearchQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<MessageSearchPage>, Response> {
+    let is_member = ChatUser::is_member(chat_id, jwt.claims.user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !is_member {
+        let err = ModelError::ClientError("You are not a member of this chat".to_string());
+        return Err(ModelError::into_error_response(err, Some(StatusCode::FORBIDDEN), None))
+    }
+
+    let page = search(&state, jwt.claims.user_id, Some(chat_id), query)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    Ok(Json(page))
+}
@@ -1,5 +1,6 @@
 use axum::{http::StatusCode, response::IntoResponse, Json};
-use serde::Serialize;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
 
 use crate::models::errors::ModelError;
 
@@ -38,4 +39,25 @@ impl ModelError {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+
+/// Binary payload transferred as a base64 string in JSON bodies
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::Type)]
+#[sqlx(transparent)]
+pub struct Base64(pub Vec<u8>);
+
+impl Serialize for Base64 {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(&self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Base64 {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded)
+            .map(Base64)
+            .map_err(serde::de::Error::custom)
+    }
+}
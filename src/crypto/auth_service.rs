@@ -1,48 +1,179 @@
-use axum::{Router, Json, routing::post};
-use openmls::prelude::tls_codec::Serialize;
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{extract::{Path, State}, http::StatusCode, response::Response, Json};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
 
-// use crate::models::{AppState, User};
-use super::mls::{generate_credential, generate_key_package};
+use crate::{
+    access::blocks,
+    app_state::AppState,
+    auth::jwt_authorization::JWTAuthorize,
+    bus::bus::{ClusterEvent, EventBus},
+    core::Base64,
+    models::{
+        device::{Device, DeviceID},
+        errors::ModelError,
+        chat::ChatUser,
+        key_package::{ClaimedKeyPackage, KeyPackageInfo, NewKeyPackage},
+        user::UserID
+    },
+    settings::{KEY_PACKAGE_LOW_STOCK_THRESHOLD, KEY_PACKAGE_MAX_BATCH, KEY_PACKAGE_MAX_PER_DEVICE}
+};
+use super::{credentials::credential_identity, mls::validate_key_package};
+
+
+#[derive(Debug, Deserialize)]
+pub struct UploadKeyPackagesForm {
+    pub device_id: DeviceID,
+    /// TLS-serialized KeyPackages
+    pub key_packages: Vec<Base64>,
+}
 
-#[derive(Deserialize)]
-pub struct RegisterRequest {
-    pub name: String,
+#[derive(Debug, Serialize)]
+pub struct UploadKeyPackagesResponse {
+    pub uploaded: usize,
+    pub stock: i64,
 }
 
-#[derive(Serialize)]
-pub struct RegisterResponse {
-    pub user_id: String,
-    pub key_package: Vec<u8>,
+#[derive(Debug, Serialize)]
+pub struct ClaimedKeyPackageResponse {
+    pub device_id: DeviceID,
+    pub key_package_ref: Base64,
+    pub key_package: Base64,
+    pub last_resort: bool,
+}
+
+
+pub async fn upload_key_packages(
+    JWTAuthorize(jwt): JWTAuthorize,
+    State(state): State<Arc<AppState>>,
+    Json(form): Json<UploadKeyPackagesForm>,
+) -> Result<(StatusCode, Json<UploadKeyPackagesResponse>), Response> {
+    let user_id = jwt.claims.user_id;
+    let client_error = |error: String| ModelError::into_error_response(ModelError::ClientError(error), None, None);
+
+    if form.key_packages.is_empty() || form.key_packages.len() > KEY_PACKAGE_MAX_BATCH {
+        return Err(client_error(format!("A batch must contain from 1 to {} KeyPackages", KEY_PACKAGE_MAX_BATCH)));
+    }
+    let device = Device::find(form.device_id, user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .ok_or_else(|| client_error("Device has not been registered".to_string()))?;
+    let stock = KeyPackageInfo::count(user_id, form.device_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+
+    let identity = credential_identity(user_id, form.device_id);
+    let mut batch = Vec::with_capacity(form.key_packages.len());
+    for (index, Base64(data)) in form.key_packages.into_iter().enumerate() {
+        let validated = validate_key_package(&data)
+            .map_err(|error| client_error(format!("KeyPackage #{}: {}", index, error)))?;
+        if validated.identity != identity {
+            return Err(client_error(format!("KeyPackage #{}: credential identity does not match the user and the device", index)));
+        }
+        if validated.signature_key != device.signature_key.0 {
+            return Err(client_error(format!("KeyPackage #{}: signature key does not match the device", index)));
+        }
+        let last_resort = validated.key_package.last_resort();
+        batch.push(NewKeyPackage {
+            id: state.snowflake_generator.generate_id().await,
+            user_id,
+            device_id: form.device_id,
+            ciphersuite: validated.key_package.ciphersuite() as u16 as i32,
+            key_package_ref: validated.key_package_ref,
+            data,
+            last_resort,
+            expires_at: DateTime::from_timestamp(validated.not_after as i64, 0).unwrap_or(DateTime::<Utc>::MAX_UTC),
+        });
+    }
+
+    let last_resort_count = batch.iter().filter(|key_package| key_package.last_resort).count();
+    if last_resort_count > 1 {
+        return Err(client_error("A batch can contain only one last-resort KeyPackage".to_string()));
+    }
+    let one_time_count = batch.len() - last_resort_count;
+    if stock as usize + one_time_count > KEY_PACKAGE_MAX_PER_DEVICE {
+        return Err(client_error(format!("A device can not store more than {} KeyPackages", KEY_PACKAGE_MAX_PER_DEVICE)));
+    }
+
+    NewKeyPackage::insert_batch(&batch, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, Some(StatusCode::CONFLICT), None))?;
+
+    Ok((StatusCode::CREATED, Json(UploadKeyPackagesResponse {
+        uploaded: batch.len(),
+        stock: stock + one_time_count as i64,
+    })))
 }
 
-static STATE: once_cell::sync::Lazy<Arc<Mutex<AppState>>> =
-    once_cell::sync::Lazy::new(|| Arc::new(Mutex::new(AppState::default())));
 
-pub fn routes() -> Router {
-    Router::new().route("/register", post(register_user))
+pub async fn list_key_packages(
+    JWTAuthorize(jwt): JWTAuthorize,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<HashMap<DeviceID, Vec<KeyPackageInfo>>>, Response> {
+    let key_packages = KeyPackageInfo::list(jwt.claims.user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+
+    let mut devices: HashMap<DeviceID, Vec<KeyPackageInfo>> = HashMap::new();
+    for key_package in key_packages {
+        devices.entry(key_package.device_id).or_default().push(key_package);
+    }
+    Ok(Json(devices))
 }
 
-async fn register_user(Json(payload): Json<RegisterRequest>) -> Json<RegisterResponse> {
-    let (cred, signer) = generate_credential(&payload.name);
-    let kp_bundle = generate_key_package(cred.clone(), &signer);
 
-    let kp = kp_bundle.key_package().clone();
-    let serialized = kp
-        .tls_serialize_detached()
-        .expect("serialization failed");
+/// Claims one KeyPackage for every device of the user, so all of them can be added to a group.
+/// A device which has run out of one-time KeyPackages hands out its last-resort one.
+/// Only the user itself and users sharing a chat with the user can claim, at a limited rate.
+pub async fn claim_key_packages(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path(user_id): Path<UserID>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<ClaimedKeyPackageResponse>>, Response> {
+    let claimer_id = jwt.claims.user_id;
+    if claimer_id != user_id {
+        let share_chat = ChatUser::share_chat(claimer_id, user_id, &state.pool)
+            .await
+            .map_err(|err| ModelError::into_error_response(err, None, None))?;
+        if !share_chat {
+            let err = ModelError::ClientError("You can only claim KeyPackages of users you share a chat with".to_string());
+            return Err(ModelError::into_error_response(err, Some(StatusCode::FORBIDDEN), None))
+        }
+        // KeyPackages are claimed to add the devices to a group
+        blocks::check_can_add(&state, claimer_id, &[user_id])
+            .await
+            .map_err(|err| ModelError::into_error_response(err, Some(StatusCode::FORBIDDEN), None))?;
+    }
+    let key_packages = ClaimedKeyPackage::claim_all(user_id, claimer_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .ok_or_else(|| {
+            let err = ModelError::ClientError("Too many KeyPackages claimed, try again later".to_string());
+            ModelError::into_error_response(err, Some(StatusCode::TOO_MANY_REQUESTS), None)
+        })?;
 
-    let user = User {
-        id: Uuid::new_v4(),
-        name: payload.name.clone(),
-        key_package: Some(kp),
-    };
+    let mut claimed = Vec::with_capacity(key_packages.len());
+    for key_package in key_packages {
+        // The device is notified once by the claim which took its stock below the threshold,
+        // it is reminded again on every connection
+        let threshold = *KEY_PACKAGE_LOW_STOCK_THRESHOLD;
+        if key_package.stock_before >= threshold && key_package.stock_after < threshold {
+            state.bus.publish(ClusterEvent::KeyPackageStockLow { user_id, device_id: key_package.device_id, stock: key_package.stock_after }).await;
+        }
+        claimed.push(ClaimedKeyPackageResponse {
+            device_id: key_package.device_id,
+            key_package_ref: Base64(key_package.key_package_ref),
+            key_package: Base64(key_package.data),
+            last_resort: key_package.last_resort,
+        });
+    }
+    Ok(Json(claimed))
+}
 
-    STATE.lock().unwrap().users.insert(payload.name.clone(), user);
 
-    Json(RegisterResponse {
-        user_id: payload.name,
-        key_package: serialized,
-    })
+/// One-time KeyPackage stock of the device, if it should upload more KeyPackages
+pub async fn low_stock(state: &Arc<AppState>, user_id: UserID, device_id: DeviceID) -> Option<i64> {
+    let stock = KeyPackageInfo::count(user_id, device_id, &state.pool).await.ok()?;
+    (stock < *KEY_PACKAGE_LOW_STOCK_THRESHOLD).then_some(stock)
 }
@@ -0,0 +1,370 @@
+// Binding of MLS credentials to accounts.
+//
+// A `BasicCredential` identity must be `"{user_id}:{device_id}"` of the device which owns the leaf.
+// openmls does not expose the content of a `PublicMessage` without the group state, so
+// handshake messages are read here with a minimal RFC 9420 decoder, which only looks for leaf nodes.
+
+use crate::models::{device::DeviceID, user::UserID};
+
+
+const WIRE_FORMAT_PUBLIC_MESSAGE: u16 = 1;
+const CREDENTIAL_TYPE_BASIC: u16 = 1;
+
+const CONTENT_TYPE_PROPOSAL: u8 = 2;
+const CONTENT_TYPE_COMMIT: u8 = 3;
+
+
+/// Credential identity the leaves of the device must carry
+pub fn credential_identity(user_id: UserID, device_id: DeviceID) -> Vec<u8> {
+    format!("{}:{}", user_id, device_id).into_bytes()
+}
+
+
+/// Credentials carried by a handshake message
+#[derive(Debug, Default)]
+pub struct HandshakeCredentials {
+    /// Identity of the committer's new leaf in the `UpdatePath`
+    pub path_identity: Option<Vec<u8>>,
+    /// Identities of the leaves in Update proposals, they replace the sender's own leaf
+    pub update_identities: Vec<Vec<u8>>,
+    /// TLS-serialized KeyPackages of Add proposals
+    pub added_key_packages: Vec<Vec<u8>>,
+    /// Extensions of the leaves and of GroupContextExtensions proposals
+    pub extension_types: Vec<u16>,
+}
+
+/// Reads the credentials of a Proposal or a Commit sent as a TLS-serialized `PublicMessage`
+pub fn inspect_public_handshake(bytes: &[u8]) -> Result<HandshakeCredentials, String> {
+    let mut reader = TlsReader::new(bytes);
+    let mut credentials = HandshakeCredentials::default();
+
+    reader.u16()?; // version
+    if reader.u16()? != WIRE_FORMAT_PUBLIC_MESSAGE {
+        return Err("Handshake messages must be sent as PublicMessage".to_string())
+    }
+    reader.vector()?; // group_id
+    reader.u64()?; // epoch
+    match reader.u8()? {
+        // member, external
+        1 | 2 => { reader.u32()?; }
+        // new_member_proposal, new_member_commit
+        3 | 4 => {}
+        other => return Err(format!("Unknown sender type {}", other)),
+    }
+    reader.vector()?; // authenticated_data
+
+    match reader.u8()? {
+        CONTENT_TYPE_PROPOSAL => read_proposal(&mut reader, &mut credentials)?,
+        CONTENT_TYPE_COMMIT => read_commit(&mut reader, &mut credentials)?,
+        _ => return Err("Message is not a Proposal or a Commit".to_string()),
+    }
+    Ok(credentials)
+}
+
+
+fn read_commit(reader: &mut TlsReader, credentials: &mut HandshakeCredentials) -> Result<(), String> {
+    let mut proposals = TlsReader::new(reader.vector()?);
+    while !proposals.is_empty() {
+        match proposals.u8()? {
+            // proposal
+            1 => read_proposal(&mut proposals, credentials)?,
+            // reference, the proposal itself has been checked when it was sent
+            2 => { proposals.vector()?; }
+            other => return Err(format!("Unknown ProposalOrRef type {}", other)),
+        }
+    }
+
+    match reader.u8()? {
+        0 => {}
+        // The path nodes follow the leaf, they carry no credentials
+        1 => credentials.path_identity = Some(read_leaf_node(reader, credentials)?),
+        _ => return Err("Malformed UpdatePath".to_string()),
+    }
+    Ok(())
+}
+
+fn read_proposal(reader: &mut TlsReader, credentials: &mut HandshakeCredentials) -> Result<(), String> {
+    match reader.u16()? {
+        // add
+        1 => {
+            let key_package = read_key_package(reader, credentials)?.to_vec();
+            credentials.added_key_packages.push(key_package);
+        }
+        // update
+        2 => {
+            let identity = read_leaf_node(reader, credentials)?;
+            credentials.update_identities.push(identity);
+        }
+        // remove
+        3 => { reader.u32()?; }
+        // psk
+        4 => {
+            match reader.u8()? {
+                1 => { reader.vector()?; }
+                2 => { reader.u8()?; reader.vector()?; reader.u64()?; }
+                other => return Err(format!("Unknown PSK type {}", other)),
+            }
+            reader.vector()?; // psk_nonce
+        }
+        // reinit
+        5 => { reader.vector()?; reader.u16()?; reader.u16()?; reader.vector()?; }
+        // external_init
+        6 => { reader.vector()?; }
+        // group_context_extensions
+        7 => read_extensions(reader, credentials)?,
+        other => return Err(format!("Unsupported proposal type {}", other)),
+    }
+    Ok(())
+}
+
+fn read_key_package<'a>(reader: &mut TlsReader<'a>, credentials: &mut HandshakeCredentials) -> Result<&'a [u8], String> {
+    let start = reader.bytes;
+    reader.u16()?; // version
+    reader.u16()?; // cipher_suite
+    reader.vector()?; // init_key
+    read_leaf_node(reader, credentials)?;
+    reader.vector()?; // extensions
+    reader.vector()?; // signature
+    Ok(&start[..start.len() - reader.bytes.len()])
+}
+
+/// Returns the identity of the leaf's credential
+fn read_leaf_node(reader: &mut TlsReader, credentials: &mut HandshakeCredentials) -> Result<Vec<u8>, String> {
+    reader.vector()?; // encryption_key
+    reader.vector()?; // signature_key
+    if reader.u16()? != CREDENTIAL_TYPE_BASIC {
+        return Err("Only basic credentials are supported".to_string())
+    }
+    let identity = reader.vector()?.to_vec();
+    // capabilities: versions, cipher_suites, extensions, proposals, credentials
+    for _ in 0..5 {
+        reader.vector()?;
+    }
+    match reader.u8()? {
+        // key_package: lifetime
+        1 => { reader.u64()?; reader.u64()?; }
+        // update
+        2 => {}
+        // commit: parent_hash
+        3 => { reader.vector()?; }
+        other => return Err(format!("Unknown leaf node source {}", other)),
+    }
+    read_extensions(reader, credentials)?;
+    reader.vector()?; // signature
+    Ok(identity)
+}
+
+fn read_extensions(reader: &mut TlsReader, credentials: &mut HandshakeCredentials) -> Result<(), String> {
+    let mut extensions = TlsReader::new(reader.vector()?);
+    while !extensions.is_empty() {
+        credentials.extension_types.push(extensions.u16()?);
+        extensions.vector()?; // extension_data
+    }
+    Ok(())
+}
+
+
+struct TlsReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> TlsReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < length {
+            return Err("Malformed MLS message: unexpected end of data".to_string())
+        }
+        let (head, tail) = self.bytes.split_at(length);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Variable-length integer of RFC 9420 section 2.1.2
+    fn varint(&mut self) -> Result<usize, String> {
+        let first = self.u8()?;
+        let length = match first >> 6 {
+            0 => 1,
+            1 => 2,
+            2 => 4,
+            _ => return Err("Malformed MLS message: invalid variable-length integer".to_string()),
+        };
+        let mut value = (first & 0x3f) as usize;
+        for _ in 1..length {
+            value = (value << 8) | self.u8()? as usize;
+        }
+        Ok(value)
+    }
+
+    fn vector(&mut self) -> Result<&'a [u8], String> {
+        let length = self.varint()?;
+        self.take(length)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use openmls::{prelude::*, prelude::tls_codec::Serialize};
+    use openmls_basic_credential::SignatureKeyPair;
+
+    use super::{credential_identity, inspect_public_handshake, TlsReader};
+    use crate::crypto::mls::{ciphersuite, generate_credential, generate_key_package, provider};
+
+    struct Member {
+        identity: Vec<u8>,
+        credential: CredentialWithKey,
+        signer: SignatureKeyPair,
+    }
+
+    fn member(user_id: i64, device_id: i64) -> Member {
+        let identity = credential_identity(user_id, device_id);
+        let (credential, signer) = generate_credential(std::str::from_utf8(&identity).unwrap());
+        Member { identity, credential, signer }
+    }
+
+    fn key_package(member: &Member) -> KeyPackage {
+        generate_key_package(member.credential.clone(), &member.signer)
+            .key_package()
+            .clone()
+    }
+
+    fn group(provider: &impl OpenMlsProvider, owner: &Member) -> MlsGroup {
+        MlsGroup::builder()
+            .ciphersuite(ciphersuite())
+            .with_wire_format_policy(PURE_PLAINTEXT_WIRE_FORMAT_POLICY)
+            .build(provider, &owner.signer, owner.credential.clone())
+            .unwrap()
+    }
+
+    fn serialize(message: &MlsMessageOut) -> Vec<u8> {
+        message.tls_serialize_detached().unwrap()
+    }
+
+    fn add_commit() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let provider = provider();
+        let alice = member(1, 1);
+        let bob = member(2, 1);
+        let bob_key_package = key_package(&bob);
+
+        let mut group = group(&provider, &alice);
+        let (commit, _, _) = group.add_members(&provider, &alice.signer, std::slice::from_ref(&bob_key_package)).unwrap();
+        (serialize(&commit), alice.identity, bob_key_package.tls_serialize_detached().unwrap())
+    }
+
+    #[test]
+    fn add_commit_carries_key_package_and_path() {
+        let (commit, alice_identity, bob_key_package) = add_commit();
+
+        let credentials = inspect_public_handshake(&commit).unwrap();
+        assert_eq!(credentials.added_key_packages, vec![bob_key_package]);
+        assert_eq!(credentials.path_identity, Some(alice_identity));
+        assert!(credentials.update_identities.is_empty());
+    }
+
+    #[test]
+    fn self_update_commit_carries_path() {
+        let provider = provider();
+        let alice = member(1, 1);
+        let mut group = group(&provider, &alice);
+
+        let bundle = group.self_update(&provider, &alice.signer, LeafNodeParameters::default()).unwrap();
+        let credentials = inspect_public_handshake(&serialize(bundle.commit())).unwrap();
+        assert_eq!(credentials.path_identity, Some(alice.identity));
+        assert!(credentials.added_key_packages.is_empty());
+    }
+
+    #[test]
+    fn add_proposal_carries_key_package() {
+        let provider = provider();
+        let alice = member(1, 1);
+        let bob = member(2, 3);
+        let bob_key_package = key_package(&bob);
+        let mut group = group(&provider, &alice);
+
+        let (proposal, _) = group.propose_add_member(&provider, &alice.signer, &bob_key_package).unwrap();
+        let credentials = inspect_public_handshake(&serialize(&proposal)).unwrap();
+        assert_eq!(credentials.added_key_packages, vec![bob_key_package.tls_serialize_detached().unwrap()]);
+        assert_eq!(credentials.path_identity, None);
+    }
+
+    #[test]
+    fn update_proposal_carries_identity() {
+        let provider = provider();
+        let alice = member(1, 2);
+        let mut group = group(&provider, &alice);
+
+        let (proposal, _) = group.propose_self_update(&provider, &alice.signer, LeafNodeParameters::default()).unwrap();
+        let credentials = inspect_public_handshake(&serialize(&proposal)).unwrap();
+        assert_eq!(credentials.update_identities, vec![alice.identity]);
+        assert!(credentials.added_key_packages.is_empty());
+    }
+
+    #[test]
+    fn truncated_messages_are_rejected() {
+        let (commit, _, _) = add_commit();
+        for length in 0..commit.len() {
+            // The path nodes and the signature are not read, a truncation can only pass if it cuts them
+            if let Ok(credentials) = inspect_public_handshake(&commit[..length]) {
+                assert_eq!(credentials.added_key_packages.len(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_length_prefixes_are_rejected() {
+        // version, wire format, group_id with a 4-byte length far beyond the data
+        let message = [0, 1, 0, 1, 0xbf, 0xff, 0xff, 0xff, 1, 2, 3];
+        assert!(inspect_public_handshake(&message).is_err());
+
+        let mut reader = TlsReader::new(&[0x40, 0x05, 1, 2]);
+        assert!(reader.vector().is_err());
+
+        // The two high bits set are reserved by RFC 9420
+        let mut reader = TlsReader::new(&[0xc0, 0, 0, 0, 0, 0, 0, 1, 0]);
+        assert!(reader.vector().is_err());
+    }
+
+    #[test]
+    fn oversized_inner_length_prefix_is_rejected() {
+        let (mut commit, _, _) = add_commit();
+        // version, wire format, group_id, epoch, sender, authenticated_data, content type
+        let mut reader = TlsReader::new(&commit);
+        reader.u16().unwrap();
+        reader.u16().unwrap();
+        reader.vector().unwrap();
+        reader.u64().unwrap();
+        reader.u8().unwrap();
+        reader.u32().unwrap();
+        reader.vector().unwrap();
+        reader.u8().unwrap();
+        let proposals_offset = commit.len() - reader.bytes.len();
+
+        // Proposals vector claiming more bytes than the message holds
+        let width = 1 << (commit[proposals_offset] >> 6);
+        commit.splice(proposals_offset..proposals_offset + width, [0xbf, 0xff, 0xff, 0xff]);
+        assert!(inspect_public_handshake(&commit).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use std::sync::Arc;
+
+use axum::{extract::{Path, Query, State}, response::Response, Json};
+use openmls::prelude::ContentType;
+use serde::Deserialize;
+
+use crate::{
+    access::blocks,
+    app_state::AppState,
+    auth::jwt_authorization::JWTAuthorize,
+    bus::bus::{ClusterEvent, EventBus},
+    models::{
+        chat::{Chat, ChatID, ChatUser},
+        device::DeviceID,
+        errors::ModelError,
+        key_package::KeyPackageClaim,
+        mls::{MlsGroup, MlsMessage, MlsMessageDTO, MlsWelcome, MlsWelcomeDTO, NewMlsGroup, NewMlsMessage, NewMlsWelcome},
+        user::UserID
+    },
+    settings::{CHAT_MAX_MEMBERS, MLS_HISTORY_PAGE_SIZE}
+};
+use super::{
+    credentials::{credential_identity, inspect_public_handshake},
+    mls::{self, inspect_protocol_message, validate_key_package, welcome_new_members, ProtocolMessageInfo},
+    policy
+};
+
+
+#[derive(Debug, Deserialize)]
+pub struct MlsHistoryQuery {
+    #[serde(default)]
+    pub after: i64,
+    pub limit: Option<i64>,
+}
+
+
+/// Registers the group of the chat, `ciphersuite` defaults to the preferred one of the policy
+pub async fn create_group(state: &Arc<AppState>, user_id: UserID, chat_id: ChatID, group_id: Vec<u8>, ciphersuite: Option<u16>) -> Result<(), ModelError> {
+    if group_id.is_empty() {
+        return Err(ModelError::ClientError("Group id is empty".to_string()))
+    }
+    let ciphersuite = ciphersuite.unwrap_or(mls::ciphersuite() as u16);
+    policy::check_ciphersuite(ciphersuite)
+        .map_err(ModelError::ClientError)?;
+    if !ChatUser::is_member(chat_id, user_id, &state.pool).await? {
+        return Err(ModelError::ClientError("You are not a member of this chat".to_string()))
+    }
+    NewMlsGroup { group_id, chat_id, ciphersuite: ciphersuite as i32 }.insert(&state.pool).await
+}
+
+
+/// Stores a handshake message and fans it out to the members of the group's chat.
+/// Application messages are chat messages, they are sent with `chat::messages::send_encrypted_message`.
+pub async fn relay(state: &Arc<AppState>, user_id: UserID, device_id: DeviceID, data: Vec<u8>) -> Result<MlsMessage, ModelError> {
+    let info = inspect_protocol_message(&data)
+        .map_err(ModelError::ClientError)?;
+    if info.content_type == ContentType::Application {
+        return Err(ModelError::ClientError("Application messages must be sent to the chat".to_string()))
+    }
+    let group = MlsGroup::find(&info.group_id, &state.pool)
+        .await?
+        .ok_or_else(|| ModelError::ClientError("MLS group not found".to_string()))?;
+    if !ChatUser::is_member(group.chat_id, user_id, &state.pool).await? {
+        return Err(ModelError::ClientError("You are not a member of this group".to_string()))
+    }
+    // A group on a suite removed from the policy has to be re-created by its members
+    policy::check_ciphersuite(group.ciphersuite as u16)
+        .map_err(ModelError::ClientError)?;
+    // A block in a direct chat also stops the group from moving on
+    let chat = Chat::find(group.chat_id, &state.pool)
+        .await?
+        .ok_or_else(|| ModelError::ClientError("Chat not found".to_string()))?;
+    blocks::check_can_post(state, user_id, &chat).await?;
+    verify_handshake_credentials(state, user_id, device_id, group.ciphersuite, &info, &data).await?;
+
+    let message = NewMlsMessage {
+        id: state.snowflake_generator.generate_id().await,
+        group_id: group.group_id,
+        sender_id: user_id,
+        epoch: info.epoch as i64,
+        content_type: info.content_type as u8 as i16,
+        data,
+    }
+        .insert(&state.pool)
+        .await?;
+
+    state.bus.publish(ClusterEvent::MlsMessageCreated { chat_id: message.chat_id, message_id: message.id }).await;
+    Ok(message)
+}
+
+
+/// Rejects handshake messages introducing leaves which are not bound to their owners.
+/// The sender may only change its own leaf, added members must come from KeyPackages it has claimed
+/// for the ciphersuite of the group.
+async fn verify_handshake_credentials(state: &Arc<AppState>, user_id: UserID, device_id: DeviceID, ciphersuite: i32, info: &ProtocolMessageInfo, data: &[u8]) -> Result<(), ModelError> {
+    if !info.is_public {
+        return Err(ModelError::ClientError("Proposals and Commits must be sent as PublicMessage".to_string()))
+    }
+    let credentials = inspect_public_handshake(data)
+        .map_err(ModelError::ClientError)?;
+    policy::check_extension_types(credentials.extension_types.iter().copied())
+        .map_err(ModelError::ClientError)?;
+
+    // The sender's own leaf must stay bound to the device the socket is authorized for
+    let own_identity = credential_identity(user_id, device_id);
+    let own_identities = credentials.path_identity.iter().chain(credentials.update_identities.iter());
+    for identity in own_identities {
+        if identity != &own_identity {
+            return Err(ModelError::ClientError("Leaf credential does not belong to the sending device".to_string()))
+        }
+    }
+
+    for key_package in credentials.added_key_packages.iter() {
+        let validated = validate_key_package(key_package)
+            .map_err(ModelError::ClientError)?;
+        if validated.key_package.ciphersuite() as u16 as i32 != ciphersuite {
+            return Err(ModelError::ClientError("Added KeyPackage is for another ciphersuite than the group".to_string()))
+        }
+        let claim = KeyPackageClaim::find(&validated.key_package_ref, user_id, &state.pool)
+            .await?
+            .ok_or_else(|| ModelError::ClientError("Added KeyPackage has not been claimed through the directory".to_string()))?;
+        if validated.identity != credential_identity(claim.user_id, claim.device_id) {
+            return Err(ModelError::ClientError("Added KeyPackage credential does not belong to its owner".to_string()))
+        }
+    }
+    Ok(())
+}
+
+
+/// Puts the Welcome into the mailbox of every device it is encrypted to.
+/// Only the user who claimed a KeyPackage can welcome its device, the recipients become members of the chat.
+pub async fn route_welcome(state: &Arc<AppState>, user_id: UserID, chat_id: ChatID, data: Vec<u8>) -> Result<usize, ModelError> {
+    let key_package_refs = welcome_new_members(&data)
+        .map_err(ModelError::ClientError)?;
+    if MlsGroup::find_by_chat(chat_id, &state.pool).await?.is_none() {
+        return Err(ModelError::ClientError("The chat has no MLS group".to_string()))
+    }
+    let member_ids = ChatUser::member_ids(chat_id, &state.pool).await?;
+    if !member_ids.contains(&user_id) {
+        return Err(ModelError::ClientError("You are not a member of this chat".to_string()))
+    }
+    let chat = Chat::find(chat_id, &state.pool)
+        .await?
+        .ok_or_else(|| ModelError::ClientError("Chat not found".to_string()))?;
+
+    let mut batch = Vec::with_capacity(key_package_refs.len());
+    for key_package_ref in key_package_refs {
+        let claim = KeyPackageClaim::find(&key_package_ref, user_id, &state.pool)
+            .await?
+            .ok_or_else(|| ModelError::ClientError("The Welcome is encrypted to a KeyPackage you have not claimed".to_string()))?;
+        batch.push(NewMlsWelcome {
+            id: state.snowflake_generator.generate_id().await,
+            key_package_ref,
+            user_id: claim.user_id,
+            device_id: claim.device_id,
+            chat_id,
+            sender_id: user_id,
+            data: data.clone(),
+        });
+    }
+
+    // Recipients join the chat, so they follow the rules for members added at creation
+    let mut joining_ids: Vec<UserID> = batch.iter()
+        .map(|welcome| welcome.user_id)
+        .filter(|recipient_id| !member_ids.contains(recipient_id))
+        .collect();
+    joining_ids.sort_unstable();
+    joining_ids.dedup();
+    let max_members = if chat.is_direct { 2 } else { CHAT_MAX_MEMBERS };
+    if member_ids.len() + joining_ids.len() > max_members {
+        return Err(ModelError::ClientError(format!("A chat can not have more than {} members", max_members)))
+    }
+    blocks::check_can_add(state, user_id, &joining_ids).await?;
+    NewMlsWelcome::insert_batch(&batch, &state.pool).await?;
+
+    for welcome in batch.iter() {
+        state.bus.publish(ClusterEvent::WelcomeCreated { user_id: welcome.user_id, welcome_id: welcome.id }).await;
+    }
+    Ok(batch.len())
+}
+
+
+/// Welcomes which arrived while the device was offline
+pub async fn pending_welcomes(state: &Arc<AppState>, user_id: UserID, device_id: DeviceID) -> Vec<MlsWelcomeDTO> {
+    match MlsWelcome::pending(user_id, device_id, &state.pool).await {
+        Ok(welcomes) => welcomes.into_iter().map(MlsWelcomeDTO::from).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+
+pub async fn acknowledge_welcome(state: &Arc<AppState>, user_id: UserID, device_id: DeviceID, welcome_id: i64) -> Result<(), ModelError> {
+    if !MlsWelcome::acknowledge(welcome_id, user_id, device_id, &state.pool).await? {
+        return Err(ModelError::ClientError("Welcome not found".to_string()))
+    }
+    Ok(())
+}
+
+
+/// Messages a member has missed, so its group state can catch up
+pub async fn list_messages(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path(chat_id): Path<ChatID>,
+    Query(query): Query<MlsHistoryQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<MlsMessageDTO>>, Response> {
+    let is_member = ChatUser::is_member(chat_id, jwt.claims.user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !is_member {
+        let err = ModelError::ClientError("You are not a member of this chat".to_string());
+        return Err(ModelError::into_error_response(err, Some(axum::http::StatusCode::FORBIDDEN), None))
+    }
+
+    let limit = query.limit.unwrap_or(MLS_HISTORY_PAGE_SIZE).clamp(1, MLS_HISTORY_PAGE_SIZE);
+    let messages = MlsMessage::list(chat_id, query.after, limit, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    Ok(Json(messages.into_iter().map(MlsMessageDTO::from).collect()))
+}
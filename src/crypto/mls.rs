@@ -5,9 +5,15 @@ use openmls::{
 use openmls_basic_credential::SignatureKeyPair;
 use openmls_rust_crypto::OpenMlsRustCrypto;
 
+use super::policy;
 
+
+/// The preferred ciphersuite of the server policy
 pub fn ciphersuite() -> Ciphersuite {
-    Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519
+    policy::allowed_ciphersuites()
+        .first()
+        .copied()
+        .unwrap_or(Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519)
 }
 
 pub fn provider() -> OpenMlsRustCrypto {
@@ -39,3 +45,86 @@ pub fn generate_key_package(
         .build(ciphersuite(), &provider(), signer, credential_with_key)
         .unwrap()
 }
+
+
+/// KeyPackage which passed the validation together with the data the directory stores
+pub struct ValidatedKeyPackage {
+    pub key_package: KeyPackage,
+    pub key_package_ref: Vec<u8>,
+    pub identity: Vec<u8>,
+    pub signature_key: Vec<u8>,
+    /// Seconds since the Unix epoch
+    pub not_after: u64,
+}
+
+/// Deserializes a TLS-serialized KeyPackage and checks its signature, lifetime and compliance with the policy
+pub fn validate_key_package(bytes: &[u8]) -> Result<ValidatedKeyPackage, String> {
+    let provider = provider();
+    let key_package_in = KeyPackageIn::tls_deserialize_exact(bytes)
+        .map_err(|e| format!("Malformed KeyPackage: {:?}", e))?;
+    let key_package = key_package_in
+        .validate(provider.crypto(), ProtocolVersion::Mls10)
+        .map_err(|e| format!("Invalid KeyPackage: {:?}", e))?;
+
+    policy::check_key_package(&key_package)?;
+    let lifetime = key_package.life_time();
+    if !lifetime.is_valid() {
+        return Err("KeyPackage lifetime is not valid at the moment".to_string());
+    }
+
+    let credential = BasicCredential::try_from(key_package.leaf_node().credential().clone())
+        .map_err(|_| "Only basic credentials are supported".to_string())?;
+    let key_package_ref = key_package
+        .hash_ref(provider.crypto())
+        .map_err(|e| format!("Failed to compute KeyPackage reference: {:?}", e))?;
+
+    Ok(ValidatedKeyPackage {
+        key_package_ref: key_package_ref.as_slice().to_vec(),
+        identity: credential.identity().to_vec(),
+        signature_key: key_package.leaf_node().signature_key().as_slice().to_vec(),
+        not_after: lifetime.not_after(),
+        key_package,
+    })
+}
+
+
+/// Unencrypted framing of a handshake or application message, all the delivery service can see
+pub struct ProtocolMessageInfo {
+    pub is_public: bool,
+    pub group_id: Vec<u8>,
+    pub epoch: u64,
+    pub content_type: ContentType,
+}
+
+/// Deserializes a TLS-serialized `MlsMessageOut` carrying a `PublicMessage` or a `PrivateMessage`
+pub fn inspect_protocol_message(bytes: &[u8]) -> Result<ProtocolMessageInfo, String> {
+    let message = MlsMessageIn::tls_deserialize_exact(bytes)
+        .map_err(|e| format!("Malformed MLS message: {:?}", e))?;
+    let protocol_message = message
+        .try_into_protocol_message()
+        .map_err(|_| "Only PublicMessage and PrivateMessage can be relayed".to_string())?;
+
+    Ok(ProtocolMessageInfo {
+        is_public: matches!(protocol_message, ProtocolMessage::PublicMessage(_)),
+        group_id: protocol_message.group_id().as_slice().to_vec(),
+        epoch: protocol_message.epoch().as_u64(),
+        content_type: protocol_message.content_type(),
+    })
+}
+
+
+/// References of the KeyPackages a TLS-serialized `Welcome` message is encrypted to
+pub fn welcome_new_members(bytes: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let message = MlsMessageIn::tls_deserialize_exact(bytes)
+        .map_err(|e| format!("Malformed MLS message: {:?}", e))?;
+
+    match message.extract() {
+        MlsMessageBodyIn::Welcome(welcome) => Ok(
+            welcome.secrets()
+                .iter()
+                .map(|secrets| secrets.new_member().as_slice().to_vec())
+                .collect()
+        ),
+        _ => Err("Message is not a Welcome".to_string()),
+    }
+}
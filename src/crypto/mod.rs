@@ -1,2 +1,5 @@
 pub mod mls;
-pub mod auth_service;
\ No newline at end of file
+pub mod auth_service;
+pub mod credentials;
+pub mod policy;
+pub mod delivery_service;
\ No newline at end of file
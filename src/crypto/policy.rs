@@ -0,0 +1,66 @@
+use openmls::prelude::{Ciphersuite, Extensions, KeyPackage};
+use axum::Json;
+use serde::Serialize;
+
+use crate::settings::{MLS_CIPHERSUITES, MLS_EXTENSIONS};
+
+
+#[derive(Debug, Serialize)]
+pub struct CiphersuiteInfo {
+    pub id: u16,
+    pub name: String,
+}
+
+/// Server policy advertised to clients, so they create KeyPackages and groups it accepts
+#[derive(Debug, Serialize)]
+pub struct MlsCapabilities {
+    /// Preferred first, new groups should use the first one the client supports
+    pub ciphersuites: Vec<CiphersuiteInfo>,
+    pub extensions: Vec<u16>,
+}
+
+
+/// Ciphersuites in the order of preference.
+/// A suite is migrated away from by adding its successor first and removing it once clients have upgraded.
+pub fn allowed_ciphersuites() -> Vec<Ciphersuite> {
+    MLS_CIPHERSUITES.iter()
+        .filter_map(|id| Ciphersuite::try_from(*id).ok())
+        .collect()
+}
+
+pub fn check_ciphersuite(id: u16) -> Result<(), String> {
+    if !allowed_ciphersuites().iter().any(|ciphersuite| *ciphersuite as u16 == id) {
+        return Err(format!("Ciphersuite 0x{:04x} is not allowed", id))
+    }
+    Ok(())
+}
+
+pub fn check_extension_types(extension_types: impl IntoIterator<Item = u16>) -> Result<(), String> {
+    for extension_type in extension_types {
+        if !MLS_EXTENSIONS.contains(&extension_type) {
+            return Err(format!("Extension 0x{:04x} is not allowed", extension_type))
+        }
+    }
+    Ok(())
+}
+
+pub fn check_key_package(key_package: &KeyPackage) -> Result<(), String> {
+    check_ciphersuite(key_package.ciphersuite() as u16)?;
+    check_extension_types(extension_types(key_package.extensions()))?;
+    check_extension_types(extension_types(key_package.leaf_node().extensions()))
+}
+
+fn extension_types(extensions: &Extensions) -> impl Iterator<Item = u16> + '_ {
+    extensions.iter().map(|extension| u16::from(extension.extension_type()))
+}
+
+
+pub async fn capabilities() -> Json<MlsCapabilities> {
+    Json(MlsCapabilities {
+        ciphersuites: allowed_ciphersuites()
+            .into_iter()
+            .map(|ciphersuite| CiphersuiteInfo { id: ciphersuite as u16, name: format!("{:?}", ciphersuite) })
+            .collect(),
+        extensions: MLS_EXTENSIONS.clone(),
+    })
+}
@@ -1,5 +1,6 @@
 use axum::response::IntoResponse;
-use axum::routing::post;
+use axum::extract::DefaultBodyLimit;
+use axum::routing::{delete, patch, post, put};
 use axum::{
     routing::get,
     Router,
@@ -9,20 +10,30 @@ use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgPoolOptions;
 use tracing::Level;
 use std::{
-    collections::HashMap, env, sync::Arc
+    collections::HashMap, env, net::SocketAddr, sync::Arc
 };
-use tokio::sync::{RwLock, broadcast};
+use tokio::sync::RwLock;
 use dotenv::dotenv;
 
 use crate::auth::ticket::TicketService;
+use crate::bus::bus::EventBusBackend;
+use crate::storage::storage::StorageBackend;
 use crate::models::AppPool;
-use crate::settings::MAX_CONNECTIONS;
-use crate::{app_state::AppState, cache::cache::TimedCache, models::prelude::SnowflakeGenerator};
+use crate::cache::cache::CacheBackendKind;
+use crate::models::worker::{LeaseValidity, WorkerID, WorkerLease};
+use crate::settings::{ATTACHMENT_CHUNK_SIZE, AVATAR_MAX_BYTES, CACHE_BACKEND, MAX_CONNECTIONS, SNOWFLAKE_HEARTBEAT_INTERVAL, SNOWFLAKE_LEASE_LIFETIME, SNOWFLAKE_MAX_WORKER_ID, SNOWFLAKE_MIN_HEARTBEATS_PER_LEASE, SNOWFLAKE_WORKER_ID};
+use crate::{app_state::AppState, models::prelude::SnowflakeGenerator};
 
 mod websocket;
 mod app_state;
 mod cache;
 mod auth;
+mod bus;
+mod chat;
+mod users;
+mod access;
+mod media;
+mod storage;
 pub mod schema;
 pub mod models;
 pub mod settings;
@@ -64,34 +75,90 @@ async fn main() {
     //     .finish();
     // tracing::subscriber::set_global_default(subscriber).unwrap();
 
-    let (tx, _rx) = broadcast::channel(100);
     let pool = create_pg_pool().await;
 
+    if *CACHE_BACKEND == CacheBackendKind::Postgres {
+        cache::postgres::spawn_purge_task(pool.clone());
+    }
+
+    let (worker_id, lease_validity) = obtain_worker_id(&pool).await;
+    let snowflake_generator = SnowflakeGenerator::new(worker_id, lease_validity);
+    tracing::debug!("{:?}", snowflake_generator);
+
     let state = AppState {
         clients: Arc::new(RwLock::new(HashMap::new())),
-        tickets: Arc::new(TicketService::default()),
+        tickets: Arc::new(TicketService::new(&pool).await),
         users: Arc::new(RwLock::new(HashMap::new())),
-        snowflake_generator: Arc::new(SnowflakeGenerator::new()),
-        tx: tx,
+        snowflake_generator: Arc::new(snowflake_generator),
+        bus: Arc::new(EventBusBackend::from_settings(&pool).await),
+        storage: Arc::new(StorageBackend::from_settings().await),
         pool: pool,
     };
+    let state = Arc::new(state);
+    websocket::dispatcher::spawn_dispatcher(state.clone());
+    media::attachments::spawn_cleanup_task(state.clone());
 
     let app = Router::new()
         // .route("/ws", get(ws_handler))
         .route("/ws", get(websocket::websocket_handler))
         .route("/signup", post(auth::auth::sign_up))
         .route("/login", post(auth::auth::login))
-        .route("/ticket", post(auth::auth::ticket))
+        .route("/logout", post(auth::auth::logout))
+        .route("/password", post(auth::auth::change_password))
+        .route("/devices", post(auth::devices::register_device).get(auth::devices::list_devices))
+        .route("/devices/{device_id}", delete(auth::devices::remove_device))
+        .route("/ticket", post(auth::auth::ticket).get(auth::auth::list_tickets))
         .route("/ticket/revoke", post(auth::auth::revoke_ticket))
+        .route("/ticket/revoke_all", post(auth::auth::revoke_all_tickets))
+        .route("/mls/capabilities", get(crypto::policy::capabilities))
+        .route("/key_packages", post(crypto::auth_service::upload_key_packages).get(crypto::auth_service::list_key_packages))
+        .route("/me", get(users::profile::me).patch(users::profile::update_me))
+        .route(
+            "/me/avatar",
+            put(media::avatar::upload_my_avatar)
+                .delete(media::avatar::delete_my_avatar)
+                .layer(DefaultBodyLimit::max(AVATAR_MAX_BYTES))
+        )
+        .route("/avatars/{hash}/{size}", get(media::avatar::serve_avatar))
+        .route("/attachments", post(media::attachments::create_attachment))
+        .route("/attachments/{attachment_id}", get(media::attachments::attachment_status))
+        .route(
+            "/attachments/{attachment_id}/chunks/{index}",
+            put(media::attachments::upload_chunk).layer(DefaultBodyLimit::max(ATTACHMENT_CHUNK_SIZE))
+        )
+        .route("/attachments/{attachment_id}/complete", post(media::attachments::complete_upload))
+        .route("/attachments/{attachment_id}/content", get(media::attachments::download_attachment))
+        .route("/blocks", get(users::blocks::list_blocks))
+        .route("/blocks/{user_id}", put(users::blocks::block_user).delete(users::blocks::unblock_user))
+        .route("/users/search", get(users::search::search_users))
+        .route("/users/{user_id}", get(users::profile::profile))
+        .route("/users/{user_id}/key_packages/claim", post(crypto::auth_service::claim_key_packages))
+        .route("/chats", post(chat::chat::create_chat))
+        .route("/mentions", get(chat::chat::mentions))
+        .route("/messages/search", get(chat::search::search_messages))
+        .route("/chats/{chat_id}", patch(chat::chat::update_chat))
+        .route("/chats/{chat_id}/messages", get(chat::chat::history))
+        .route("/chats/{chat_id}/messages/search", get(chat::search::search_chat_messages))
+        .route("/chats/{chat_id}/mute", put(chat::chat::mute_chat).delete(chat::chat::unmute_chat))
+        .route("/chats/{chat_id}/pins", get(chat::pins::list_pins))
+        .route("/chats/{chat_id}/pins/{message_id}", put(chat::pins::pin_message).delete(chat::pins::unpin_message))
+        .route("/chats/{chat_id}/messages/{message_id}/thread", get(chat::chat::thread))
+        .route(
+            "/chats/{chat_id}/avatar",
+            put(media::avatar::upload_chat_avatar)
+                .delete(media::avatar::delete_chat_avatar)
+                .layer(DefaultBodyLimit::max(AVATAR_MAX_BYTES))
+        )
+        .route("/chats/{chat_id}/mls/messages", get(crypto::delivery_service::list_messages))
         .route("/ping", get(ping))
-        .with_state(Arc::new(state));
+        .with_state(state);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8000")
         .await
         .unwrap();
     
     tracing::debug!("Listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
 }
 
 
@@ -106,6 +173,35 @@ async fn create_pg_pool() -> AppPool {
 }
 
 
+// Fails the startup rather than risking duplicate ids
+async fn obtain_worker_id(pool: &AppPool) -> (u64, LeaseValidity) {
+    // A few heartbeats may fail in a row without losing the lease
+    assert!(
+        *SNOWFLAKE_LEASE_LIFETIME >= SNOWFLAKE_HEARTBEAT_INTERVAL * SNOWFLAKE_MIN_HEARTBEATS_PER_LEASE,
+        "`SNOWFLAKE_LEASE_LIFETIME` must be at least {:?}",
+        SNOWFLAKE_HEARTBEAT_INTERVAL * SNOWFLAKE_MIN_HEARTBEATS_PER_LEASE
+    );
+    // A fixed id is leased as well, so a leasing instance can not pick it
+    let requested = SNOWFLAKE_WORKER_ID.map(|worker_id| {
+        assert!(worker_id <= SNOWFLAKE_MAX_WORKER_ID, "`SNOWFLAKE_WORKER_ID` must not exceed {}", SNOWFLAKE_MAX_WORKER_ID);
+        worker_id as WorkerID
+    });
+
+    let lease = match WorkerLease::acquire(requested, pool).await {
+        Ok(Some(_lease)) => _lease,
+        Ok(None) => match requested {
+            Some(worker_id) => panic!("Snowflake worker id `{}` is leased by another instance", worker_id),
+            None => panic!("No free snowflake worker id, all of them are leased by other instances"),
+        },
+        Err(_) => panic!("Failed to lease a snowflake worker id"),
+    };
+    let worker_id = lease.worker_id as u64;
+    let validity = lease.validity();
+    lease.spawn_heartbeat(pool.clone());
+    (worker_id, validity)
+}
+
+
 async fn ping() -> impl IntoResponse {
     "Pong!"
 }
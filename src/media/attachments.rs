@@ -0,0 +1,367 @@
+use std::{io, pin::Pin, sync::Arc, task::{Context, Poll}};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+use crate::{
+    app_state::AppState,
+    auth::jwt_authorization::JWTAuthorize,
+    models::{
+        attachment::{Attachment, AttachmentChunk, AttachmentID, NewAttachment},
+        chat::{ChatID, ChatUser},
+        errors::ModelError,
+        user::UserID
+    },
+    settings::{
+        ATTACHMENT_CHUNK_SIZE, ATTACHMENT_CLEANUP_INTERVAL, ATTACHMENT_FILE_NAME_MAX_LENGTH, ATTACHMENT_MAX_PENDING_BYTES,
+        ATTACHMENT_MAX_PENDING_UPLOADS, ATTACHMENT_MAX_SIZE, ATTACHMENT_UNSENT_LIFETIME, ATTACHMENT_UPLOAD_LIFETIME
+    },
+    storage::storage::{stream_body, BlobReader, BlobStore}
+};
+
+
+#[derive(Debug, Deserialize)]
+pub struct NewAttachmentForm {
+    pub chat_id: ChatID,
+    pub file_name: String,
+    pub mime_type: String,
+    pub size: i64,
+    /// Hex SHA-256 of the whole file
+    pub checksum: String,
+}
+
+/// State of an upload, so an interrupted one can be resumed
+#[derive(Debug, Serialize)]
+pub struct AttachmentUpload {
+    pub attachment: Attachment,
+    pub chunk_count: i32,
+    pub received_chunks: Vec<i32>,
+}
+
+
+fn chunk_key(attachment_id: AttachmentID, index: i32) -> String {
+    format!("attachments/{}/chunks/{}", attachment_id, index)
+}
+
+fn content_key(attachment_id: AttachmentID) -> String {
+    format!("attachments/{}/content", attachment_id)
+}
+
+fn chunk_count(attachment: &Attachment) -> i32 {
+    ((attachment.size + attachment.chunk_size as i64 - 1) / attachment.chunk_size as i64) as i32
+}
+
+fn is_valid_mime_type(mime_type: &str) -> bool {
+    let token = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || "!#$&^_.+-".contains(c));
+    match mime_type.split_once('/') {
+        Some((kind, subtype)) => token(kind) && token(subtype),
+        None => false,
+    }
+}
+
+async fn upload_status(state: &Arc<AppState>, attachment: Attachment) -> Result<AttachmentUpload, ModelError> {
+    let received_chunks = AttachmentChunk::received(attachment.id, &state.pool).await?;
+    Ok(AttachmentUpload { chunk_count: chunk_count(&attachment), received_chunks, attachment })
+}
+
+/// Computes the SHA-256 and the size of the content as it is read
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    size: i64,
+}
+
+impl<R> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, hasher: Sha256::new(), size: 0 }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for HashingReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let this = &mut *self;
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            let read = &buf.filled()[filled..];
+            this.hasher.update(read);
+            this.size += read.len() as i64;
+        }
+        poll
+    }
+}
+
+/// Returns the attachment if it has been created by the user
+async fn find_own_attachment(state: &Arc<AppState>, user_id: UserID, attachment_id: AttachmentID) -> Result<Attachment, Response> {
+    Attachment::find(attachment_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .filter(|attachment| attachment.uploader_id == user_id)
+        .ok_or_else(|| {
+            let err = ModelError::ClientError("Attachment not found".to_string());
+            ModelError::into_error_response(err, Some(StatusCode::NOT_FOUND), None)
+        })
+}
+
+
+/// Starts an upload, the file is then sent in chunks of `chunk_size` bytes
+pub async fn create_attachment(
+    JWTAuthorize(jwt): JWTAuthorize,
+    State(state): State<Arc<AppState>>,
+    Json(form): Json<NewAttachmentForm>,
+) -> Result<(StatusCode, Json<AttachmentUpload>), Response> {
+    let user_id = jwt.claims.user_id;
+    let client_error = |error: String| ModelError::into_error_response(ModelError::ClientError(error), None, None);
+
+    let file_name = form.file_name.trim();
+    if file_name.is_empty() || file_name.chars().count() > ATTACHMENT_FILE_NAME_MAX_LENGTH {
+        return Err(client_error(format!("File name must contain from 1 to {} characters", ATTACHMENT_FILE_NAME_MAX_LENGTH)));
+    }
+    let mime_type = form.mime_type.trim().to_lowercase();
+    if !is_valid_mime_type(&mime_type) {
+        return Err(client_error("Invalid MIME type".to_string()));
+    }
+    if form.size <= 0 || form.size > ATTACHMENT_MAX_SIZE {
+        return Err(client_error(format!("File size must be from 1 to {} bytes", ATTACHMENT_MAX_SIZE)));
+    }
+    let checksum = form.checksum.trim().to_lowercase();
+    if checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(client_error("Checksum must be a hex SHA-256".to_string()));
+    }
+
+    let is_member = ChatUser::is_member(form.chat_id, user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !is_member {
+        let err = ModelError::ClientError("You are not a member of this chat".to_string());
+        return Err(ModelError::into_error_response(err, Some(StatusCode::FORBIDDEN), None))
+    }
+
+    let attachment = NewAttachment {
+        id: state.snowflake_generator.generate_id().await,
+        uploader_id: user_id,
+        chat_id: form.chat_id,
+        file_name: file_name.to_string(),
+        mime_type,
+        size: form.size,
+        checksum,
+        chunk_size: ATTACHMENT_CHUNK_SIZE as i32,
+    }
+        .insert(ATTACHMENT_MAX_PENDING_UPLOADS, ATTACHMENT_MAX_PENDING_BYTES, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .ok_or_else(|| {
+            let err = ModelError::ClientError(format!(
+                "A user can not have more than {} unfinished uploads of {} bytes in total", ATTACHMENT_MAX_PENDING_UPLOADS, ATTACHMENT_MAX_PENDING_BYTES
+            ));
+            ModelError::into_error_response(err, Some(StatusCode::TOO_MANY_REQUESTS), None)
+        })?;
+
+    let upload = upload_status(&state, attachment)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    Ok((StatusCode::CREATED, Json(upload)))
+}
+
+
+pub async fn attachment_status(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path(attachment_id): Path<AttachmentID>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<AttachmentUpload>, Response> {
+    let attachment = find_own_attachment(&state, jwt.claims.user_id, attachment_id).await?;
+    let upload = upload_status(&state, attachment)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    Ok(Json(upload))
+}
+
+
+/// Stores a chunk, a chunk sent again replaces the previous one.
+/// Chunks are not accepted anymore once the upload is being completed
+pub async fn upload_chunk(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path((attachment_id, index)): Path<(AttachmentID, i32)>,
+    State(state): State<Arc<AppState>>,
+    data: Bytes,
+) -> Result<StatusCode, Response> {
+    let attachment = find_own_attachment(&state, jwt.claims.user_id, attachment_id).await?;
+    let client_error = |error: String| ModelError::into_error_response(ModelError::ClientError(error), None, None);
+
+    if attachment.completed_at.is_some() {
+        return Err(client_error("The upload has already been completed".to_string()));
+    }
+    if index < 0 || index >= chunk_count(&attachment) {
+        return Err(client_error(format!("Chunk index must be from 0 to {}", chunk_count(&attachment) - 1)));
+    }
+    // Every chunk but the last one is exactly `chunk_size` bytes
+    let offset = index as i64 * attachment.chunk_size as i64;
+    let expected = (attachment.size - offset).min(attachment.chunk_size as i64);
+    if data.len() as i64 != expected {
+        return Err(client_error(format!("Chunk {} must be {} bytes", index, expected)));
+    }
+
+    let recorded = AttachmentChunk::record(attachment_id, index, expected as i32, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !recorded {
+        return Err(client_error("The upload is being completed".to_string()));
+    }
+    state.storage.put(&chunk_key(attachment_id, index), data.as_ref())
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+/// Streams the chunks into the content one after another, the checksum is computed on the way
+async fn assemble_content(state: &Arc<AppState>, attachment: &Attachment, chunks: i32) -> Result<(), Response> {
+    let client_error = |error: String| ModelError::into_error_response(ModelError::ClientError(error), None, None);
+    let mut content: BlobReader = Box::pin(tokio::io::empty());
+    for index in 0..chunks {
+        let chunk = state.storage.get(&chunk_key(attachment.id, index))
+            .await
+            .map_err(|err| ModelError::into_error_response(err, None, None))?
+            .ok_or_else(|| client_error(format!("Chunk {} is missing, it has to be uploaded again", index)))?;
+        content = Box::pin(content.chain(chunk));
+    }
+    let mut content = HashingReader::new(content);
+    state.storage.put(&content_key(attachment.id), &mut content)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if content.size != attachment.size || format!("{:x}", content.hasher.finalize()) != attachment.checksum {
+        let _ = state.storage.delete(&content_key(attachment.id)).await;
+        return Err(client_error("Checksum does not match the uploaded content".to_string()));
+    }
+    Ok(())
+}
+
+
+/// Assembles the chunks and verifies the checksum, after that the attachment can be sent in messages
+pub async fn complete_upload(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path(attachment_id): Path<AttachmentID>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Attachment>, Response> {
+    let attachment = find_own_attachment(&state, jwt.claims.user_id, attachment_id).await?;
+    let client_error = |error: String| ModelError::into_error_response(ModelError::ClientError(error), None, None);
+    if attachment.completed_at.is_some() {
+        return Err(client_error("The upload has already been completed".to_string()));
+    }
+
+    let chunks = chunk_count(&attachment);
+    let received = AttachmentChunk::received(attachment_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if received.len() as i32 != chunks {
+        return Err(client_error(format!("{} of {} chunks have been received", received.len(), chunks)));
+    }
+
+    // Only one request assembles the chunks, no chunk is accepted meanwhile
+    let started = Attachment::start_completion(attachment_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !started {
+        return Err(client_error("The upload is being or has already been completed".to_string()));
+    }
+    if let Err(response) = assemble_content(&state, &attachment, chunks).await {
+        let _ = Attachment::abort_completion(attachment_id, &state.pool).await;
+        return Err(response);
+    }
+    let completed = Attachment::complete(attachment_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !completed {
+        return Err(client_error("The upload has already been completed".to_string()));
+    }
+
+    for index in 0..chunks {
+        let _ = state.storage.delete(&chunk_key(attachment_id, index)).await;
+    }
+    let _ = AttachmentChunk::delete_all(attachment_id, &state.pool).await;
+
+    let attachment = find_own_attachment(&state, jwt.claims.user_id, attachment_id).await?;
+    Ok(Json(attachment))
+}
+
+
+/// Content of a completed attachment, only for members of its chat
+pub async fn download_attachment(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path(attachment_id): Path<AttachmentID>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, Response> {
+    let not_found = || {
+        let err = ModelError::ClientError("Attachment not found".to_string());
+        ModelError::into_error_response(err, Some(StatusCode::NOT_FOUND), None)
+    };
+    let attachment = Attachment::find(attachment_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .filter(|attachment| attachment.completed_at.is_some())
+        .ok_or_else(not_found)?;
+    // Attachments of other chats are reported as missing, so their ids can not be probed
+    let accessible = Attachment::is_accessible(attachment_id, jwt.claims.user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !accessible {
+        return Err(not_found())
+    }
+
+    let content = state.storage.get(&content_key(attachment_id))
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .ok_or_else(not_found)?;
+
+    let file_name: String = attachment.file_name.chars()
+        .map(|c| if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' { c } else { '_' })
+        .collect();
+    Ok((
+        [
+            (header::CONTENT_TYPE, attachment.mime_type),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file_name)),
+            (header::CONTENT_LENGTH, attachment.size.to_string()),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        stream_body(content)
+    ).into_response())
+}
+
+
+/// Periodically deletes the uploads which have not been completed in time, with their chunks,
+/// and the attachments which have not been sent in any message
+pub fn spawn_cleanup_task(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(ATTACHMENT_CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Ok(attachments) = Attachment::delete_stale(ATTACHMENT_UPLOAD_LIFETIME, &state.pool).await else {
+                continue
+            };
+            for attachment in &attachments {
+                for index in 0..chunk_count(attachment) {
+                    let _ = state.storage.delete(&chunk_key(attachment.id, index)).await;
+                }
+                // Left behind if the server stopped between assembling and completing the upload
+                let _ = state.storage.delete(&content_key(attachment.id)).await;
+            }
+            tracing::debug!("Attachment cleanup: {} stale upload(s) deleted", attachments.len());
+
+            let Ok(attachments) = Attachment::delete_unsent(ATTACHMENT_UNSENT_LIFETIME, &state.pool).await else {
+                continue
+            };
+            for attachment in &attachments {
+                let _ = state.storage.delete(&content_key(attachment.id)).await;
+            }
+            tracing::debug!("Attachment cleanup: {} unsent attachment(s) deleted", attachments.len());
+        }
+    });
+}
@@ -0,0 +1,273 @@
+use std::{io::Cursor, sync::Arc};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json
+};
+use image::{imageops::FilterType, DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+use sha2::{Digest, Sha256};
+use tokio::task;
+
+use crate::{
+    app_state::AppState,
+    auth::jwt_authorization::JWTAuthorize,
+    bus::bus::{ClusterEvent, EventBus},
+    models::{chat::{Chat, ChatID, ChatUser}, errors::ModelError, user::{User, UserProfile}},
+    settings::{AVATAR_MAX_ALLOC, AVATAR_MAX_DIMENSION, AVATAR_SIZES},
+    storage::storage::{stream_body, BlobStore}
+};
+
+
+/// Square PNG images of the avatar, one for every size of `AVATAR_SIZES`
+struct ProcessedAvatar {
+    /// SHA-256 of the largest image, it addresses all of them
+    hash: String,
+    images: Vec<(u32, Vec<u8>)>,
+}
+
+fn avatar_key(hash: &str, size: u32) -> String {
+    format!("avatars/{}/{}.png", hash, size)
+}
+
+/// Decodes the uploaded image and re-encodes it, so metadata and anything
+/// hidden after the image data is dropped
+fn process_avatar(data: &[u8]) -> Result<ProcessedAvatar, String> {
+    // The content type sent by the client is not trusted
+    let format = image::guess_format(data)
+        .map_err(|_| "Unrecognized image format".to_string())?;
+    if !matches!(format, ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP | ImageFormat::Gif) {
+        return Err("Only PNG, JPEG, WebP and GIF images are supported".to_string())
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(AVATAR_MAX_DIMENSION);
+    limits.max_image_height = Some(AVATAR_MAX_DIMENSION);
+    limits.max_alloc = Some(AVATAR_MAX_ALLOC);
+    let mut reader = ImageReader::with_format(Cursor::new(data), format);
+    reader.limits(limits);
+
+    let mut decoder = reader.into_decoder()
+        .map_err(|e| format!("Invalid image: {}", e))?;
+    let orientation = decoder.orientation()
+        .map_err(|e| format!("Invalid image: {}", e))?;
+    let mut image = DynamicImage::from_decoder(decoder)
+        .map_err(|e| format!("Invalid image: {}", e))?;
+    image.apply_orientation(orientation);
+
+    let side = image.width().min(image.height());
+    let image = image.crop_imm((image.width() - side) / 2, (image.height() - side) / 2, side, side);
+
+    let mut images = Vec::with_capacity(AVATAR_SIZES.len());
+    for &size in AVATAR_SIZES.iter() {
+        let resized = if side > size { image.resize_exact(size, size, FilterType::Lanczos3) } else { image.clone() };
+        let mut encoded = Vec::new();
+        DynamicImage::ImageRgba8(resized.to_rgba8())
+            .write_to(&mut Cursor::new(&mut encoded), ImageFormat::Png)
+            .map_err(|e| {
+                tracing::error!("{:?}", e);
+                "Failed to encode the image".to_string()
+            })?;
+        images.push((size, encoded));
+    }
+
+    let (_, largest) = images.iter().max_by_key(|(size, _)| *size).unwrap();
+    let hash = format!("{:x}", Sha256::digest(largest));
+    Ok(ProcessedAvatar { hash, images })
+}
+
+/// Processes the upload and stores its images, returns the content hash
+async fn store_avatar(state: &Arc<AppState>, data: Bytes) -> Result<String, ModelError> {
+    if data.is_empty() {
+        return Err(ModelError::ClientError("Image is empty".to_string()))
+    }
+    let avatar = task::spawn_blocking(move || process_avatar(&data))
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            ModelError::UnexpectedError("Internal server error".to_string())
+        })?
+        .map_err(ModelError::ClientError)?;
+
+    // Identical avatars share their images, old ones are left in place as other records may use them
+    for (size, image) in avatar.images {
+        state.storage.put(&avatar_key(&avatar.hash, size), image.as_slice()).await?;
+    }
+    Ok(avatar.hash)
+}
+
+
+async fn set_user_avatar(state: &Arc<AppState>, user_id: i64, avatar: Option<String>) -> Result<Json<UserProfile>, Response> {
+    let profile = User::update_avatar(user_id, avatar, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .ok_or_else(|| {
+            let err = ModelError::ClientError("User not found".to_string());
+            ModelError::into_error_response(err, Some(StatusCode::NOT_FOUND), None)
+        })?;
+
+    state.bus.publish(ClusterEvent::ProfileUpdated { user_id }).await;
+    Ok(Json(profile))
+}
+
+
+pub async fn upload_my_avatar(
+    JWTAuthorize(jwt): JWTAuthorize,
+    State(state): State<Arc<AppState>>,
+    data: Bytes,
+) -> Result<Json<UserProfile>, Response> {
+    let hash = store_avatar(&state, data)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    set_user_avatar(&state, jwt.claims.user_id, Some(hash)).await
+}
+
+
+pub async fn delete_my_avatar(
+    JWTAuthorize(jwt): JWTAuthorize,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<UserProfile>, Response> {
+    set_user_avatar(&state, jwt.claims.user_id, None).await
+}
+
+
+async fn set_chat_avatar(state: &Arc<AppState>, user_id: i64, chat_id: ChatID, data: Option<Bytes>) -> Result<Json<Chat>, Response> {
+    let is_owner = ChatUser::is_owner(chat_id, user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !is_owner {
+        let err = ModelError::ClientError("Only owners can change the chat".to_string());
+        return Err(ModelError::into_error_response(err, Some(StatusCode::FORBIDDEN), None))
+    }
+
+    let avatar = match data {
+        Some(data) => Some(
+            store_avatar(state, data)
+                .await
+                .map_err(|err| ModelError::into_error_response(err, None, None))?
+        ),
+        None => None,
+    };
+    let chat = Chat::update_avatar(chat_id, avatar, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .ok_or_else(|| {
+            let err = ModelError::ClientError("Chat not found".to_string());
+            ModelError::into_error_response(err, Some(StatusCode::NOT_FOUND), None)
+        })?;
+
+    state.bus.publish(ClusterEvent::ChatUpdated { chat_id }).await;
+    Ok(Json(chat))
+}
+
+
+pub async fn upload_chat_avatar(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path(chat_id): Path<ChatID>,
+    State(state): State<Arc<AppState>>,
+    data: Bytes,
+) -> Result<Json<Chat>, Response> {
+    set_chat_avatar(&state, jwt.claims.user_id, chat_id, Some(data)).await
+}
+
+
+pub async fn delete_chat_avatar(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path(chat_id): Path<ChatID>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Chat>, Response> {
+    set_chat_avatar(&state, jwt.claims.user_id, chat_id, None).await
+}
+
+
+/// Serves an avatar image by its content hash, the content never changes so it is cached forever
+pub async fn serve_avatar(
+    Path((hash, size)): Path<(String, u32)>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let valid_hash = hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase());
+    if !valid_hash || !AVATAR_SIZES.contains(&size) {
+        return StatusCode::NOT_FOUND.into_response()
+    }
+    match state.storage.get(&avatar_key(&hash, size)).await {
+        Ok(Some(image)) => (
+            [
+                (header::CONTENT_TYPE, "image/png"),
+                (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+            ],
+            stream_body(image)
+        ).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => ModelError::into_error_response(err, None, None),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{DynamicImage, ImageFormat, Rgb, RgbImage, Rgba};
+    use sha2::{Digest, Sha256};
+
+    use super::process_avatar;
+
+    const RED: Rgb<u8> = Rgb([255, 0, 0]);
+    const BLUE: Rgb<u8> = Rgb([0, 0, 255]);
+
+    /// Blue square of `side` pixels between red bands of `band` pixels on the left and on the right
+    fn encoded_image(side: u32, band: u32, format: ImageFormat) -> Vec<u8> {
+        let image = RgbImage::from_fn(side + 2 * band, side, |x, _| if x < band || x >= band + side { RED } else { BLUE });
+        let mut encoded = Vec::new();
+        DynamicImage::ImageRgb8(image)
+            .write_to(&mut Cursor::new(&mut encoded), format)
+            .unwrap();
+        encoded
+    }
+
+    #[test]
+    fn crops_to_the_center_square_and_resizes() {
+        let Ok(avatar) = process_avatar(&encoded_image(300, 100, ImageFormat::Png)) else {
+            panic!("avatar rejected")
+        };
+        let sizes: Vec<u32> = avatar.images.iter().map(|(size, _)| *size).collect();
+        assert_eq!(sizes, [64, 256, 512]);
+
+        for (size, encoded) in &avatar.images {
+            let image = image::load_from_memory_with_format(encoded, ImageFormat::Png).unwrap().to_rgba8();
+            // Smaller images are not upscaled
+            let side = (*size).min(300);
+            assert_eq!(image.dimensions(), (side, side), "{}", size);
+            // The red bands are cut off
+            for (x, y) in [(0, 0), (side - 1, 0), (0, side - 1), (side - 1, side - 1), (side / 2, side / 2)] {
+                assert_eq!(*image.get_pixel(x, y), Rgba([0, 0, 255, 255]), "{} at {}x{}", size, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn addresses_the_images_by_the_hash_of_the_largest() {
+        let Ok(avatar) = process_avatar(&encoded_image(600, 50, ImageFormat::Jpeg)) else {
+            panic!("avatar rejected")
+        };
+        let (_, largest) = avatar.images.iter().find(|(size, _)| *size == 512).unwrap();
+        assert_eq!(avatar.hash, format!("{:x}", Sha256::digest(largest)));
+
+        // The same upload gives the same images
+        let Ok(again) = process_avatar(&encoded_image(600, 50, ImageFormat::Jpeg)) else {
+            panic!("avatar rejected")
+        };
+        assert_eq!(again.hash, avatar.hash);
+    }
+
+    #[test]
+    fn rejects_unsupported_and_broken_images() {
+        assert!(process_avatar(b"BM\x3a\0\0\0\0\0\0\0\x36\0\0\0").is_err());
+        assert!(process_avatar(b"not an image").is_err());
+        let mut truncated = encoded_image(64, 0, ImageFormat::Png);
+        truncated.truncate(truncated.len() / 2);
+        assert!(process_avatar(&truncated).is_err());
+    }
+}
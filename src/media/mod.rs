@@ -0,0 +1,2 @@
+pub mod avatar;
+pub mod attachments;
@@ -0,0 +1,350 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::models::{chat::ChatID, errors::ModelError, user::UserID};
+
+
+pub type AttachmentID = i64;
+
+#[derive(Debug, Clone, sqlx::FromRow, Serialize)]
+pub struct Attachment {
+    pub id: AttachmentID,
+    pub uploader_id: UserID,
+    pub chat_id: ChatID,
+    pub file_name: String,
+    pub mime_type: String,
+    pub size: i64,
+    /// Lowercase hex SHA-256 of the file
+    pub checksum: String,
+    pub chunk_size: i32,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Attachment as it is shown in a message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachmentInfo {
+    pub id: AttachmentID,
+    pub file_name: String,
+    pub mime_type: String,
+    pub size: i64,
+    pub checksum: String,
+}
+
+impl Attachment {
+    pub async fn find(attachment_id: AttachmentID, pool: &super::AppPool) -> Result<Option<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            Attachment,
+            r#"
+                SELECT id, uploader_id, chat_id, file_name, mime_type, size, checksum, chunk_size, completed_at, created_at
+                FROM attachments
+                WHERE id = $1;
+            "#,
+            attachment_id
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match result {
+            Ok(_attachment) => Ok(_attachment),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Number of the attachments which the user has completely uploaded to the chat
+    pub async fn count_attachable(attachment_ids: &[AttachmentID], uploader_id: UserID, chat_id: ChatID, pool: &super::AppPool) -> Result<i64, ModelError> {
+        let result = sqlx::query_scalar!(
+            r#"
+                SELECT COUNT(*) AS "count!" FROM attachments
+                WHERE id = ANY($1) AND uploader_id = $2 AND chat_id = $3 AND completed_at IS NOT NULL;
+            "#,
+            attachment_ids,
+            uploader_id,
+            chat_id
+        )
+        .fetch_one(pool)
+        .await;
+
+        match result {
+            Ok(_count) => Ok(_count),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Whether the user is a member of the chat the attachment was uploaded to
+    /// or of a chat where it has been forwarded
+    pub async fn is_accessible(attachment_id: AttachmentID, user_id: UserID, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result = sqlx::query_scalar!(
+            r#"
+                SELECT EXISTS(
+                    SELECT 1 FROM chat_users
+                    WHERE user_id = $2 AND chat_id IN (
+                        SELECT chat_id FROM attachments WHERE id = $1
+                        UNION
+                        SELECT chat_messages.chat_id FROM message_attachments
+                        JOIN chat_messages ON chat_messages.id = message_attachments.message_id
+                        WHERE message_attachments.attachment_id = $1
+                    )
+                ) AS "accessible!";
+            "#,
+            attachment_id,
+            user_id
+        )
+        .fetch_one(pool)
+        .await;
+
+        match result {
+            Ok(_accessible) => Ok(_accessible),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Claims the upload for assembling its chunks, returns `false` if it is being or has been completed
+    pub async fn start_completion(attachment_id: AttachmentID, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result = sqlx::query!(
+            "UPDATE attachments SET completing = TRUE WHERE id = $1 AND completed_at IS NULL AND NOT completing",
+            attachment_id
+        )
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(_result) => Ok(_result.rows_affected() == 1),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Releases the claim of a completion which has failed, so it can be tried again
+    pub async fn abort_completion(attachment_id: AttachmentID, pool: &super::AppPool) -> Result<(), ModelError> {
+        let result = sqlx::query!(
+            "UPDATE attachments SET completing = FALSE WHERE id = $1 AND completed_at IS NULL",
+            attachment_id
+        )
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Marks the claimed upload as completed, returns `false` if it has not been claimed
+    pub async fn complete(attachment_id: AttachmentID, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result = sqlx::query!(
+            "UPDATE attachments SET completed_at = NOW(), completing = FALSE WHERE id = $1 AND completed_at IS NULL AND completing",
+            attachment_id
+        )
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(_result) => Ok(_result.rows_affected() == 1),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Deletes the uploads which have not been completed within `lifetime`, their blobs are left to the caller
+    pub async fn delete_stale(lifetime: Duration, pool: &super::AppPool) -> Result<Vec<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            Attachment,
+            r#"
+                DELETE FROM attachments
+                WHERE completed_at IS NULL AND created_at < NOW() - make_interval(secs => $1)
+                RETURNING id, uploader_id, chat_id, file_name, mime_type, size, checksum, chunk_size, completed_at, created_at;
+            "#,
+            lifetime.as_secs_f64()
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_attachments) => Ok(_attachments),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Deletes the completed attachments which have not been sent in any message within `lifetime`,
+    /// their blobs are left to the caller
+    pub async fn delete_unsent(lifetime: Duration, pool: &super::AppPool) -> Result<Vec<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            Attachment,
+            r#"
+                DELETE FROM attachments
+                WHERE completed_at < NOW() - make_interval(secs => $1)
+                AND NOT EXISTS (SELECT 1 FROM message_attachments WHERE message_attachments.attachment_id = attachments.id)
+                RETURNING id, uploader_id, chat_id, file_name, mime_type, size, checksum, chunk_size, completed_at, created_at;
+            "#,
+            lifetime.as_secs_f64()
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_attachments) => Ok(_attachments),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NewAttachment {
+    pub id: AttachmentID,
+    pub uploader_id: UserID,
+    pub chat_id: ChatID,
+    pub file_name: String,
+    pub mime_type: String,
+    pub size: i64,
+    pub checksum: String,
+    pub chunk_size: i32,
+}
+
+impl NewAttachment {
+    /// Starts the upload unless the uploader already has `max_uploads` uploads or `max_bytes` bytes
+    /// which have not been completed, returns `None` then
+    pub async fn insert(&self, max_uploads: i64, max_bytes: i64, pool: &super::AppPool) -> Result<Option<Attachment>, ModelError> {
+        let result: Result<Option<Attachment>, sqlx::Error> = async {
+            let mut transaction = pool.begin().await?;
+            // Concurrent uploads of the user wait here, so they can not exceed the limits together
+            sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", self.uploader_id)
+                .fetch_one(&mut *transaction)
+                .await?;
+
+            let pending = sqlx::query!(
+                r#"
+                    SELECT COUNT(*) AS "uploads!", COALESCE(SUM(size), 0)::BIGINT AS "bytes!"
+                    FROM attachments
+                    WHERE uploader_id = $1 AND completed_at IS NULL;
+                "#,
+                self.uploader_id
+            )
+            .fetch_one(&mut *transaction)
+            .await?;
+            if pending.uploads >= max_uploads || pending.bytes + self.size > max_bytes {
+                return Ok(None)
+            }
+
+            let attachment = sqlx::query_as!(
+                Attachment,
+                r#"
+                    INSERT INTO
+                    attachments (id, uploader_id, chat_id, file_name, mime_type, size, checksum, chunk_size)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                    RETURNING id, uploader_id, chat_id, file_name, mime_type, size, checksum, chunk_size, completed_at, created_at;
+                "#,
+                self.id,
+                self.uploader_id,
+                self.chat_id,
+                self.file_name,
+                self.mime_type,
+                self.size,
+                self.checksum,
+                self.chunk_size
+            )
+            .fetch_one(&mut *transaction)
+            .await?;
+
+            transaction.commit().await?;
+            Ok(Some(attachment))
+        }.await;
+
+        match result {
+            Ok(_attachment) => Ok(_attachment),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
+
+/// Chunk of an upload which has been received, the content is kept in the blob store
+pub struct AttachmentChunk;
+
+impl AttachmentChunk {
+    /// Records the chunk of an upload which is not being completed, returns `false` otherwise
+    pub async fn record(attachment_id: AttachmentID, index: i32, size: i32, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result = sqlx::query!(
+            r#"
+                INSERT INTO attachment_chunks (attachment_id, chunk_index, size)
+                SELECT $1, $2, $3 FROM attachments
+                WHERE id = $1 AND completed_at IS NULL AND NOT completing
+                ON CONFLICT (attachment_id, chunk_index) DO UPDATE SET size = EXCLUDED.size;
+            "#,
+            attachment_id,
+            index,
+            size
+        )
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(_result) => Ok(_result.rows_affected() == 1),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Indexes of the received chunks in ascending order
+    pub async fn received(attachment_id: AttachmentID, pool: &super::AppPool) -> Result<Vec<i32>, ModelError> {
+        let result = sqlx::query_scalar!(
+            "SELECT chunk_index FROM attachment_chunks WHERE attachment_id = $1 ORDER BY chunk_index",
+            attachment_id
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_indexes) => Ok(_indexes),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    pub async fn delete_all(attachment_id: AttachmentID, pool: &super::AppPool) -> Result<(), ModelError> {
+        let result = sqlx::query!(
+            "DELETE FROM attachment_chunks WHERE attachment_id = $1",
+            attachment_id
+        )
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
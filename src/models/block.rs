@@ -0,0 +1,113 @@
+use crate::models::{errors::ModelError, user::{UserID, UserProfile}};
+
+
+#[derive(Debug)]
+pub struct Block {
+    pub blocker_id: UserID,
+    pub blocked_id: UserID,
+}
+
+impl Block {
+    /// Returns `false` if the user has already been blocked
+    pub async fn insert(&self, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result = sqlx::query!(
+            r#"
+                INSERT INTO user_blocks (blocker_id, blocked_id)
+                VALUES ($1, $2)
+                ON CONFLICT DO NOTHING;
+            "#,
+            self.blocker_id,
+            self.blocked_id
+        )
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(_result) => Ok(_result.rows_affected() == 1),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Returns `false` if the user has not been blocked
+    pub async fn delete(&self, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result = sqlx::query!(
+            "DELETE FROM user_blocks WHERE blocker_id = $1 AND blocked_id = $2",
+            self.blocker_id,
+            self.blocked_id
+        )
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(_result) => Ok(_result.rows_affected() == 1),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Users blocked by the user, most recently blocked first
+    pub async fn list(blocker_id: UserID, pool: &super::AppPool) -> Result<Vec<UserProfile>, ModelError> {
+        let result = sqlx::query_as!(
+            UserProfile,
+            r#"
+                SELECT users.id, users.username, users.name, users.status, users.bio, users.avatar
+                FROM user_blocks
+                JOIN users ON users.id = user_blocks.blocked_id
+                WHERE user_blocks.blocker_id = $1
+                ORDER BY user_blocks.created_at DESC;
+            "#,
+            blocker_id
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_profiles) => Ok(_profiles),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    pub async fn blocked_ids(blocker_id: UserID, pool: &super::AppPool) -> Result<Vec<UserID>, ModelError> {
+        let result = sqlx::query_scalar!(
+            "SELECT blocked_id FROM user_blocks WHERE blocker_id = $1",
+            blocker_id
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_ids) => Ok(_ids),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Those of `user_ids` who have blocked `blocked_id`
+    pub async fn blockers_among(blocked_id: UserID, user_ids: &[UserID], pool: &super::AppPool) -> Result<Vec<UserID>, ModelError> {
+        let result = sqlx::query_scalar!(
+            "SELECT blocker_id FROM user_blocks WHERE blocked_id = $1 AND blocker_id = ANY($2)",
+            blocked_id,
+            user_ids
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_ids) => Ok(_ids),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
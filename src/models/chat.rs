@@ -1,26 +1,383 @@
 use serde::Serialize;
 
+use crate::models::{errors::ModelError, user::UserID};
 
-#[derive(Debug, Clone, Serialize)]
+
+pub type ChatID = i64;
+
+#[derive(Debug, Clone, sqlx::FromRow, Serialize)]
 pub struct Chat {
-    pub id: i64,
+    pub id: ChatID,
     pub name: String,
+    /// Only MLS-encrypted payloads are accepted in the chat
+    pub encrypted: bool,
+    /// Content hash of the avatar
+    pub avatar: Option<String>,
+    /// Otherwise only owners can pin messages
+    pub members_can_pin: bool,
+    /// Chat between exactly two users, set at creation
+    pub is_direct: bool,
+}
+
+impl Chat {
+    pub async fn find(chat_id: ChatID, pool: &super::AppPool) -> Result<Option<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            Chat,
+            "SELECT id, name, encrypted, avatar, members_can_pin, is_direct FROM chats WHERE id = $1",
+            chat_id
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match result {
+            Ok(_chat) => Ok(_chat),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// The direct chat between the two users
+    pub async fn find_direct(user_id: UserID, other_id: UserID, pool: &super::AppPool) -> Result<Option<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            Chat,
+            r#"
+                SELECT chats.id, chats.name, chats.encrypted, chats.avatar, chats.members_can_pin, chats.is_direct
+                FROM direct_chats
+                JOIN chats ON chats.id = direct_chats.chat_id
+                WHERE direct_chats.first_user_id = LEAST($1::BIGINT, $2::BIGINT) AND direct_chats.second_user_id = GREATEST($1::BIGINT, $2::BIGINT);
+            "#,
+            user_id,
+            other_id
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match result {
+            Ok(_chat) => Ok(_chat),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    pub async fn update_members_can_pin(chat_id: ChatID, members_can_pin: bool, pool: &super::AppPool) -> Result<Option<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            Chat,
+            "UPDATE chats SET members_can_pin = $2 WHERE id = $1 RETURNING id, name, encrypted, avatar, members_can_pin, is_direct",
+            chat_id,
+            members_can_pin
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match result {
+            Ok(_chat) => Ok(_chat),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    pub async fn update_avatar(chat_id: ChatID, avatar: Option<String>, pool: &super::AppPool) -> Result<Option<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            Chat,
+            "UPDATE chats SET avatar = $2 WHERE id = $1 RETURNING id, name, encrypted, avatar, members_can_pin, is_direct",
+            chat_id,
+            avatar
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match result {
+            Ok(_chat) => Ok(_chat),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub struct NewChat {
-    pub id: i64,
+    pub id: ChatID,
+    /// Creator of the chat, becomes its owner
+    pub owner_id: UserID,
     pub name: String,
+    pub encrypted: bool,
+    pub is_direct: bool,
+}
+
+impl NewChat {
+    /// Creates the chat together with its members, `member_ids` must include the owner.
+    /// A direct chat is registered for the pair of its members, there is only one for every pair
+    pub async fn insert(&self, member_ids: &[UserID], pool: &super::AppPool) -> Result<Chat, ModelError> {
+        let result: Result<Chat, sqlx::Error> = async {
+            let mut transaction = pool.begin().await?;
+            let chat = sqlx::query_as!(
+                Chat,
+                "INSERT INTO chats (id, name, encrypted, is_direct) VALUES ($1, $2, $3, $4) RETURNING id, name, encrypted, avatar, members_can_pin, is_direct",
+                self.id,
+                self.name,
+                self.encrypted,
+                self.is_direct
+            )
+            .fetch_one(&mut *transaction)
+            .await?;
+
+            sqlx::query!(
+                r#"
+                    INSERT INTO chat_users (chat_id, user_id, is_owner)
+                    SELECT $1, user_id, user_id = $3 FROM UNNEST($2::BIGINT[]) AS user_id
+                    ON CONFLICT DO NOTHING;
+                "#,
+                self.id,
+                member_ids,
+                self.owner_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+
+            if self.is_direct {
+                sqlx::query!(
+                    r#"
+                        INSERT INTO direct_chats (chat_id, first_user_id, second_user_id)
+                        SELECT $1, MIN(user_id), MAX(user_id) FROM UNNEST($2::BIGINT[]) AS user_id;
+                    "#,
+                    self.id,
+                    member_ids
+                )
+                .execute(&mut *transaction)
+                .await?;
+            }
+
+            transaction.commit().await?;
+            Ok(chat)
+        }.await;
+
+        match result {
+            Ok(_chat) => Ok(_chat),
+            Err(sqlx::Error::Database(db_err)) if db_err.is_foreign_key_violation() => {
+                Err(ModelError::ClientError("User not found".to_string()))
+            }
+            Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+                Err(ModelError::ClientError("A direct chat with this user already exists".to_string()))
+            }
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ChatUser {
-    pub chat_id: i64,
-    pub user_id: i64,
+    pub chat_id: ChatID,
+    pub user_id: UserID,
+}
+
+impl ChatUser {
+    pub async fn is_member(chat_id: ChatID, user_id: UserID, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM chat_users WHERE chat_id = $1 AND user_id = $2) AS "exists!""#,
+            chat_id,
+            user_id
+        )
+        .fetch_one(pool)
+        .await;
+
+        match result {
+            Ok(_exists) => Ok(_exists),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    pub async fn is_owner(chat_id: ChatID, user_id: UserID, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM chat_users WHERE chat_id = $1 AND user_id = $2 AND is_owner) AS "exists!""#,
+            chat_id,
+            user_id
+        )
+        .fetch_one(pool)
+        .await;
+
+        match result {
+            Ok(_exists) => Ok(_exists),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Returns `false` if the user is not a member of the chat
+    pub async fn set_muted(chat_id: ChatID, user_id: UserID, muted: bool, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result = sqlx::query!(
+            "UPDATE chat_users SET muted = $3 WHERE chat_id = $1 AND user_id = $2",
+            chat_id,
+            user_id,
+            muted
+        )
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(_result) => Ok(_result.rows_affected() > 0),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Members of the chat other than the sender, referenced by username or by id
+    pub async fn resolve_mentions(chat_id: ChatID, sender_id: UserID, usernames: &[String], user_ids: &[UserID], pool: &super::AppPool) -> Result<Vec<UserID>, ModelError> {
+        let result = sqlx::query_scalar!(
+            r#"
+                SELECT users.id
+                FROM chat_users
+                JOIN users ON users.id = chat_users.user_id
+                WHERE chat_users.chat_id = $1 AND users.id <> $2 AND (users.username = ANY($3) OR users.id = ANY($4))
+                ORDER BY users.id;
+            "#,
+            chat_id,
+            sender_id,
+            usernames,
+            user_ids
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_ids) => Ok(_ids),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Other users who share at least one chat with the user
+    pub async fn peer_ids(user_id: UserID, pool: &super::AppPool) -> Result<Vec<UserID>, ModelError> {
+        let result = sqlx::query_scalar!(
+            r#"
+                SELECT DISTINCT peers.user_id
+                FROM chat_users
+                JOIN chat_users AS peers ON peers.chat_id = chat_users.chat_id
+                WHERE chat_users.user_id = $1 AND peers.user_id <> $1;
+            "#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_ids) => Ok(_ids),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Members of the chat with whether they have muted it
+    pub async fn member_mutes(chat_id: ChatID, pool: &super::AppPool) -> Result<Vec<(UserID, bool)>, ModelError> {
+        let result = sqlx::query!(
+            "SELECT user_id, muted FROM chat_users WHERE chat_id = $1",
+            chat_id
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_rows) => Ok(_rows.into_iter().map(|row| (row.user_id, row.muted)).collect()),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    pub async fn member_ids(chat_id: ChatID, pool: &super::AppPool) -> Result<Vec<UserID>, ModelError> {
+        let result = sqlx::query_scalar!(
+            "SELECT user_id FROM chat_users WHERE chat_id = $1",
+            chat_id
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_ids) => Ok(_ids),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    pub async fn share_chat(user_id: UserID, other_id: UserID, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result = sqlx::query_scalar!(
+            r#"
+                SELECT EXISTS(
+                    SELECT 1 FROM chat_users
+                    JOIN chat_users AS others ON others.chat_id = chat_users.chat_id
+                    WHERE chat_users.user_id = $1 AND others.user_id = $2
+                ) AS "exists!";
+            "#,
+            user_id,
+            other_id
+        )
+        .fetch_one(pool)
+        .await;
+
+        match result {
+            Ok(_exists) => Ok(_exists),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Users of `user_ids` whom the user can not add to a chat,
+    /// they are not discoverable and do not share a chat with the user
+    pub async fn unreachable_ids(user_id: UserID, user_ids: &[UserID], pool: &super::AppPool) -> Result<Vec<UserID>, ModelError> {
+        let result = sqlx::query_scalar!(
+            r#"
+                SELECT id FROM users
+                WHERE id = ANY($2) AND id <> $1 AND NOT discoverable
+                AND NOT EXISTS(
+                    SELECT 1 FROM chat_users
+                    JOIN chat_users AS others ON others.chat_id = chat_users.chat_id
+                    WHERE chat_users.user_id = $1 AND others.user_id = users.id
+                );
+            "#,
+            user_id,
+            user_ids
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_user_ids) => Ok(_user_ids),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub struct NewChatUser {
-    pub chat_id: i64,
-    pub user_id: i64,
+    pub chat_id: ChatID,
+    pub user_id: UserID,
 }
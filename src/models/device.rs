@@ -0,0 +1,170 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::postgres::PgDatabaseError;
+
+use crate::{core::Base64, models::{errors::ModelError, user::UserID}};
+
+
+pub type DeviceID = i64;
+
+#[derive(Debug, Clone, sqlx::FromRow, Serialize)]
+pub struct Device {
+    pub id: DeviceID,
+    pub user_id: UserID,
+    pub name: String,
+    pub platform: String,
+    /// Signature public key the device's MLS leaves must carry
+    pub signature_key: Base64,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+impl Device {
+    /// Returns the device only if it belongs to the user
+    pub async fn find(device_id: DeviceID, user_id: UserID, pool: &super::AppPool) -> Result<Option<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            Device,
+            r#"
+                SELECT id, user_id, name, platform, signature_key AS "signature_key: Base64", created_at, last_seen_at
+                FROM devices
+                WHERE id = $1 AND user_id = $2;
+            "#,
+            device_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match result {
+            Ok(_device) => Ok(_device),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    pub async fn list(user_id: UserID, pool: &super::AppPool) -> Result<Vec<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            Device,
+            r#"
+                SELECT id, user_id, name, platform, signature_key AS "signature_key: Base64", created_at, last_seen_at
+                FROM devices
+                WHERE user_id = $1
+                ORDER BY id;
+            "#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_devices) => Ok(_devices),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    pub async fn touch(device_id: DeviceID, pool: &super::AppPool) -> Result<(), ModelError> {
+        let result = sqlx::query!(
+            "UPDATE devices SET last_seen_at = NOW() WHERE id = $1",
+            device_id
+        )
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Removes the device with its KeyPackages and Welcomes, returns `false` if the user has no such device
+    pub async fn delete(device_id: DeviceID, user_id: UserID, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result = sqlx::query!(
+            "DELETE FROM devices WHERE id = $1 AND user_id = $2",
+            device_id,
+            user_id
+        )
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(_result) => Ok(_result.rows_affected() == 1),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NewDevice {
+    pub id: DeviceID,
+    pub user_id: UserID,
+    pub name: String,
+    pub platform: String,
+    pub signature_key: Vec<u8>,
+}
+
+impl NewDevice {
+    /// Registers the device unless the user already has `max_devices` devices, returns `None` then
+    pub async fn insert(&self, max_devices: i64, pool: &super::AppPool) -> Result<Option<Device>, ModelError> {
+        let result: Result<Option<Device>, sqlx::Error> = async {
+            let mut transaction = pool.begin().await?;
+            // Concurrent registrations of the user wait here, so they can not exceed the limit together
+            sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", self.user_id)
+                .fetch_one(&mut *transaction)
+                .await?;
+
+            let count = sqlx::query_scalar!(
+                r#"SELECT COUNT(*) AS "count!" FROM devices WHERE user_id = $1"#,
+                self.user_id
+            )
+            .fetch_one(&mut *transaction)
+            .await?;
+            if count >= max_devices {
+                return Ok(None)
+            }
+
+            let device = sqlx::query_as!(
+                Device,
+                r#"
+                    INSERT INTO devices (id, user_id, name, platform, signature_key)
+                    VALUES ($1, $2, $3, $4, $5)
+                    RETURNING id, user_id, name, platform, signature_key AS "signature_key: Base64", created_at, last_seen_at;
+                "#,
+                self.id,
+                self.user_id,
+                self.name,
+                self.platform,
+                self.signature_key
+            )
+            .fetch_one(&mut *transaction)
+            .await?;
+
+            transaction.commit().await?;
+            Ok(Some(device))
+        }.await;
+
+        match result {
+            Ok(_device) => Ok(_device),
+            Err(sqlx::Error::Database(db_err)) => {
+                if let Some(pg_err) = db_err.try_downcast_ref::<PgDatabaseError>() && pg_err.code() == "23505" {
+                    return Err(ModelError::ClientError("A device with this signature key has already been registered".to_string()));
+                }
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::postgres::PgDatabaseError;
+
+use crate::{
+    models::{device::DeviceID, errors::ModelError, user::UserID},
+    settings::{KEY_PACKAGE_CLAIM_WINDOW, KEY_PACKAGE_MAX_CLAIMS_PER_TARGET, KEY_PACKAGE_MAX_CLAIMS_PER_WINDOW}
+};
+
+
+/// Stored KeyPackage without its payload
+#[derive(Debug, Clone, sqlx::FromRow, Serialize)]
+pub struct KeyPackageInfo {
+    pub id: i64,
+    pub device_id: DeviceID,
+    pub ciphersuite: i32,
+    pub key_package_ref: Vec<u8>,
+    pub last_resort: bool,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ClaimedKeyPackage {
+    pub device_id: DeviceID,
+    pub key_package_ref: Vec<u8>,
+    pub data: Vec<u8>,
+    pub last_resort: bool,
+    /// One-time KeyPackages of the device before and after the claim
+    pub stock_before: i64,
+    pub stock_after: i64,
+}
+
+impl KeyPackageInfo {
+    pub async fn list(user_id: UserID, pool: &super::AppPool) -> Result<Vec<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            KeyPackageInfo,
+            r#"
+                SELECT id, device_id, ciphersuite, key_package_ref, last_resort, expires_at, created_at
+                FROM key_packages
+                WHERE user_id = $1 AND expires_at > NOW()
+                ORDER BY device_id, id;
+            "#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_key_packages) => Ok(_key_packages),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Number of one-time KeyPackages of the device, the last-resort one is not counted
+    pub async fn count(user_id: UserID, device_id: DeviceID, pool: &super::AppPool) -> Result<i64, ModelError> {
+        let result = sqlx::query_scalar!(
+            r#"
+                SELECT COUNT(*) AS "count!" FROM key_packages
+                WHERE user_id = $1 AND device_id = $2 AND NOT last_resort AND expires_at > NOW();
+            "#,
+            user_id,
+            device_id
+        )
+        .fetch_one(pool)
+        .await;
+
+        match result {
+            Ok(_count) => Ok(_count),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct KeyPackageClaim {
+    pub key_package_ref: Vec<u8>,
+    pub user_id: UserID,
+    pub device_id: DeviceID,
+    pub claimed_by: UserID,
+}
+
+impl KeyPackageClaim {
+    pub async fn find(key_package_ref: &[u8], claimed_by: UserID, pool: &super::AppPool) -> Result<Option<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            KeyPackageClaim,
+            r#"
+                SELECT key_package_ref, user_id, device_id, claimed_by
+                FROM key_package_claims
+                WHERE key_package_ref = $1 AND claimed_by = $2;
+            "#,
+            key_package_ref,
+            claimed_by
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match result {
+            Ok(_claim) => Ok(_claim),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
+
+impl ClaimedKeyPackage {
+    /// Claims a KeyPackage of every device of the user in one transaction: the oldest one-time KeyPackage,
+    /// deleted so concurrent claims never receive the same one, or the last-resort one once the device has run out.
+    /// The claims are recorded, so the Welcome made with a KeyPackage can be routed to the device.
+    /// Returns `None` if the claimer has reached the claim limits of the window.
+    pub async fn claim_all(user_id: UserID, claimed_by: UserID, pool: &super::AppPool) -> Result<Option<Vec<Self>>, ModelError> {
+        let result: Result<Option<Vec<Self>>, sqlx::Error> = async {
+            let mut transaction = pool.begin().await?;
+            // Claims by the claimer and of the user wait for each other, so the limits and the stock are exact.
+            // The rows are locked in the order of ids, two users claiming from each other can not deadlock
+            sqlx::query!(
+                "SELECT id FROM users WHERE id = ANY($1) ORDER BY id FOR UPDATE",
+                &[user_id, claimed_by][..]
+            )
+            .fetch_all(&mut *transaction)
+            .await?;
+
+            let recent = sqlx::query!(
+                r#"
+                    SELECT COUNT(*) AS "total!", COUNT(*) FILTER (WHERE user_id = $2) AS "of_user!"
+                    FROM key_package_claims
+                    WHERE claimed_by = $1 AND claimed_at > NOW() - make_interval(secs => $3);
+                "#,
+                claimed_by,
+                user_id,
+                KEY_PACKAGE_CLAIM_WINDOW.as_secs_f64()
+            )
+            .fetch_one(&mut *transaction)
+            .await?;
+            if recent.total >= KEY_PACKAGE_MAX_CLAIMS_PER_WINDOW || recent.of_user >= KEY_PACKAGE_MAX_CLAIMS_PER_TARGET {
+                return Ok(None)
+            }
+
+            let device_ids = sqlx::query_scalar!(
+                "SELECT DISTINCT device_id FROM key_packages WHERE user_id = $1 AND expires_at > NOW()",
+                user_id
+            )
+            .fetch_all(&mut *transaction)
+            .await?;
+
+            let mut claimed = Vec::with_capacity(device_ids.len());
+            for device_id in device_ids {
+                let key_package = sqlx::query_as!(
+                    ClaimedKeyPackage,
+                    r#"
+                        WITH stock AS (
+                            SELECT COUNT(*) AS count FROM key_packages
+                            WHERE user_id = $1 AND device_id = $2 AND NOT last_resort AND expires_at > NOW()
+                        ), claimed AS (
+                            DELETE FROM key_packages
+                            WHERE id = (
+                                SELECT id FROM key_packages
+                                WHERE user_id = $1 AND device_id = $2 AND NOT last_resort AND expires_at > NOW()
+                                ORDER BY id
+                                LIMIT 1
+                            )
+                            RETURNING user_id, device_id, key_package_ref, data, last_resort
+                        ), recorded AS (
+                            INSERT INTO key_package_claims (key_package_ref, user_id, device_id, claimed_by)
+                            SELECT key_package_ref, user_id, device_id, $3 FROM claimed
+                        )
+                        SELECT
+                            device_id AS "device_id!",
+                            key_package_ref AS "key_package_ref!",
+                            data AS "data!",
+                            last_resort AS "last_resort!",
+                            stock.count AS "stock_before!",
+                            stock.count - 1 AS "stock_after!"
+                        FROM claimed, stock;
+                    "#,
+                    user_id,
+                    device_id,
+                    claimed_by
+                )
+                .fetch_optional(&mut *transaction)
+                .await?;
+                if key_package.is_some() {
+                    claimed.extend(key_package);
+                    continue
+                }
+
+                // The last-resort KeyPackage is returned without deleting it
+                let key_package = sqlx::query_as!(
+                    ClaimedKeyPackage,
+                    r#"
+                        WITH claimed AS (
+                            SELECT user_id, device_id, key_package_ref, data, last_resort FROM key_packages
+                            WHERE user_id = $1 AND device_id = $2 AND last_resort AND expires_at > NOW()
+                        ), recorded AS (
+                            INSERT INTO key_package_claims (key_package_ref, user_id, device_id, claimed_by)
+                            SELECT key_package_ref, user_id, device_id, $3 FROM claimed
+                            ON CONFLICT (key_package_ref, claimed_by) DO UPDATE SET claimed_at = NOW()
+                        )
+                        SELECT
+                            device_id AS "device_id!",
+                            key_package_ref AS "key_package_ref!",
+                            data AS "data!",
+                            last_resort AS "last_resort!",
+                            0::BIGINT AS "stock_before!",
+                            0::BIGINT AS "stock_after!"
+                        FROM claimed;
+                    "#,
+                    user_id,
+                    device_id,
+                    claimed_by
+                )
+                .fetch_optional(&mut *transaction)
+                .await?;
+                claimed.extend(key_package);
+            }
+
+            transaction.commit().await?;
+            Ok(Some(claimed))
+        }.await;
+
+        match result {
+            Ok(_claimed) => Ok(_claimed),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NewKeyPackage {
+    pub id: i64,
+    pub user_id: UserID,
+    pub device_id: DeviceID,
+    pub ciphersuite: i32,
+    pub key_package_ref: Vec<u8>,
+    pub data: Vec<u8>,
+    pub last_resort: bool,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl NewKeyPackage {
+    /// Inserts the whole batch or nothing, a last-resort KeyPackage replaces the previous one
+    pub async fn insert_batch(batch: &[Self], pool: &super::AppPool) -> Result<(), ModelError> {
+        let result: Result<(), sqlx::Error> = async {
+            let mut transaction = pool.begin().await?;
+            for key_package in batch {
+                if key_package.last_resort {
+                    sqlx::query!(
+                        "DELETE FROM key_packages WHERE user_id = $1 AND device_id = $2 AND last_resort",
+                        key_package.user_id,
+                        key_package.device_id
+                    )
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+                sqlx::query!(
+                    r#"
+                        INSERT INTO
+                        key_packages (id, user_id, device_id, ciphersuite, key_package_ref, data, last_resort, expires_at)
+                        VALUES ($1, $2, $3, $4, $5, $6, $7, $8);
+                    "#,
+                    key_package.id,
+                    key_package.user_id,
+                    key_package.device_id,
+                    key_package.ciphersuite,
+                    key_package.key_package_ref,
+                    key_package.data,
+                    key_package.last_resort,
+                    key_package.expires_at
+                )
+                .execute(&mut *transaction)
+                .await?;
+            }
+            transaction.commit().await
+        }.await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(sqlx::Error::Database(db_err)) => {
+                if let Some(pg_err) = db_err.try_downcast_ref::<PgDatabaseError>() && pg_err.code() == "23505" {
+                    return Err(ModelError::ClientError("KeyPackage has already been uploaded".to_string()));
+                }
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
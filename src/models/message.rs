@@ -1,19 +1,654 @@
 use chrono::NaiveDateTime;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
 
+use crate::{core::Base64, models::{attachment::{AttachmentID, AttachmentInfo}, chat::ChatID, errors::ModelError, reaction::ReactionSummary, user::UserID}};
 
-#[derive(Debug, Clone, Serialize)]
+
+pub type MessageID = i64;
+
+/// Message of a plaintext chat has `content`, message of an encrypted chat has `payload`
+#[derive(Debug, Clone, sqlx::FromRow, Serialize)]
 pub struct Message {
-    pub id: i64,
+    pub id: MessageID,
     pub user_id: i64,
     pub chat_id: i64,
-    pub content: String,
+    pub content: Option<String>,
+    /// MLS `PrivateMessage`, opaque to the server
+    pub payload: Option<Base64>,
+    pub epoch: Option<i64>,
+    /// Leaf index of the sender in the group's ratchet tree, as reported by the sender
+    pub sender_leaf: Option<i32>,
+    pub reply_to: Option<MessageID>,
+    /// First message of the thread the reply belongs to
+    pub thread_root_id: Option<MessageID>,
+    /// Snapshot of the replied message taken when the reply was sent
+    pub quote: Option<Json<Quote>>,
+    pub forwarded_from: Option<Json<ForwardOrigin>>,
+    /// Number of replies in the thread, if the message is a thread root
+    pub reply_count: i64,
+    /// Members notified about the message even if they have muted the chat
+    pub mentions: Vec<UserID>,
+    /// Files of a plaintext message, an encrypted message carries its attachment keys in the payload
+    pub attachments: Json<Vec<AttachmentInfo>>,
+    pub reactions: Json<Vec<ReactionSummary>>,
+    /// Formatting of `content`, sorted by offset
+    pub entities: Json<Vec<MessageEntity>>,
     pub updated_at: NaiveDateTime,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Quote {
+    pub message_id: MessageID,
+    pub user_id: UserID,
+    pub content: Option<String>,
+}
+
+/// Message matching a full-text search
+#[derive(Debug, Clone, sqlx::FromRow, Serialize)]
+pub struct MessageSearchHit {
+    pub id: MessageID,
+    pub chat_id: ChatID,
+    pub user_id: UserID,
+    /// HTML-escaped fragments of the content with the matches wrapped in `<mark>`
+    pub snippet: String,
+    pub updated_at: NaiveDateTime,
+}
+
+/// Search within the plaintext chats of `searcher_id`, ids bound the time range since they are snowflakes
+#[derive(Debug)]
+pub struct MessageSearch {
+    pub query: String,
+    pub searcher_id: UserID,
+    pub chat_id: Option<ChatID>,
+    pub author_id: Option<UserID>,
+    pub min_id: Option<MessageID>,
+    pub before_id: Option<MessageID>,
+    pub limit: i64,
+}
+
+impl MessageSearch {
+    /// Matches newest first, encrypted chats are never searched
+    pub async fn run(&self, pool: &super::AppPool) -> Result<Vec<MessageSearchHit>, ModelError> {
+        let result = sqlx::query_as!(
+            MessageSearchHit,
+            r#"
+                SELECT
+                    chat_messages.id, chat_messages.chat_id, chat_messages.user_id,
+                    ts_headline(
+                        'simple',
+                        replace(replace(replace(chat_messages.content, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'),
+                        query,
+                        'StartSel=<mark>, StopSel=</mark>, MaxWords=20, MinWords=5, MaxFragments=2'
+                    ) AS "snippet!",
+                    chat_messages.updated_at
+                FROM chat_messages
+                JOIN chats ON chats.id = chat_messages.chat_id
+                JOIN chat_users ON chat_users.chat_id = chat_messages.chat_id AND chat_users.user_id = $2,
+                    websearch_to_tsquery('simple', $1) AS query
+                WHERE NOT chats.encrypted AND chat_messages.search_vector @@ query
+                    AND ($3::BIGINT IS NULL OR chat_messages.chat_id = $3)
+                    AND ($4::BIGINT IS NULL OR chat_messages.user_id = $4)
+                    AND ($5::BIGINT IS NULL OR chat_messages.id >= $5)
+                    AND ($6::BIGINT IS NULL OR chat_messages.id < $6)
+                ORDER BY chat_messages.id DESC
+                LIMIT $7;
+            "#,
+            self.query,
+            self.searcher_id,
+            self.chat_id,
+            self.author_id,
+            self.min_id,
+            self.before_id,
+            self.limit
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_hits) => Ok(_hits),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
+
+/// Formatted range of the content, `offset` and `length` are in UTF-16 code units
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageEntity {
+    pub offset: usize,
+    pub length: usize,
+    #[serde(flatten)]
+    pub kind: EntityKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EntityKind {
+    Bold,
+    Italic,
+    Code,
+    Pre { language: Option<String> },
+    Link { url: String },
+    Spoiler,
+    Mention { user_id: UserID },
+}
+
+/// Where a forwarded message comes from, the ids are hidden if the author does not allow linking
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardOrigin {
+    pub user_id: Option<UserID>,
+    pub chat_id: Option<ChatID>,
+    pub message_id: Option<MessageID>,
+    /// Name of the author when the message was forwarded
+    pub name: String,
+}
+
+impl Message {
+    pub async fn find(message_id: MessageID, pool: &super::AppPool) -> Result<Option<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            Message,
+            r#"
+                SELECT
+                    id, user_id, chat_id, content, payload AS "payload: Base64", epoch, sender_leaf,
+                    reply_to, thread_root_id,
+                    CASE WHEN reply_to IS NULL THEN NULL ELSE json_build_object(
+                        'message_id', reply_to,
+                        'user_id', quote_user_id,
+                        'content', quote_content
+                    ) END AS "quote: Json<Quote>",
+                    CASE WHEN forward_name IS NULL THEN NULL ELSE json_build_object(
+                        'user_id', forward_user_id,
+                        'chat_id', forward_chat_id,
+                        'message_id', forward_message_id,
+                        'name', forward_name
+                    ) END AS "forwarded_from: Json<ForwardOrigin>",
+                    (SELECT COUNT(*) FROM chat_messages AS replies WHERE replies.thread_root_id = chat_messages.id) AS "reply_count!",
+                    ARRAY(
+                        SELECT user_id FROM message_mentions WHERE message_mentions.message_id = chat_messages.id ORDER BY user_id
+                    ) AS "mentions!",
+                    (
+                        SELECT COALESCE(json_agg(json_build_object(
+                            'id', attachments.id,
+                            'file_name', attachments.file_name,
+                            'mime_type', attachments.mime_type,
+                            'size', attachments.size,
+                            'checksum', attachments.checksum
+                        ) ORDER BY message_attachments.position), '[]')
+                        FROM message_attachments
+                        JOIN attachments ON attachments.id = message_attachments.attachment_id
+                        WHERE message_attachments.message_id = chat_messages.id
+                    ) AS "attachments!: Json<Vec<AttachmentInfo>>",
+                    (
+                        SELECT COALESCE(json_agg(json_build_object(
+                            'emoji', summary.emoji,
+                            'count', summary.count,
+                            'reacted_by_me', summary.reacted_by_me
+                        ) ORDER BY summary.first_at), '[]')
+                        FROM (
+                            SELECT emoji, COUNT(*) AS count, FALSE AS reacted_by_me, MIN(created_at) AS first_at
+                            FROM message_reactions
+                            WHERE message_reactions.message_id = chat_messages.id
+                            GROUP BY emoji
+                        ) AS summary
+                    ) AS "reactions!: Json<Vec<ReactionSummary>>",
+                    entities AS "entities: Json<Vec<MessageEntity>>",
+                    updated_at
+                FROM chat_messages
+                WHERE id = $1;
+            "#,
+            message_id
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match result {
+            Ok(_message) => Ok(_message),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Messages of the chat sent before `before_id`, newest first.
+    /// Reactions are marked for `viewer_id`, `find` never marks them.
+    pub async fn list(chat_id: i64, viewer_id: UserID, before_id: Option<MessageID>, limit: i64, pool: &super::AppPool) -> Result<Vec<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            Message,
+            r#"
+                SELECT
+                    id, user_id, chat_id, content, payload AS "payload: Base64", epoch, sender_leaf,
+                    reply_to, thread_root_id,
+                    CASE WHEN reply_to IS NULL THEN NULL ELSE json_build_object(
+                        'message_id', reply_to,
+                        'user_id', quote_user_id,
+                        'content', quote_content
+                    ) END AS "quote: Json<Quote>",
+                    CASE WHEN forward_name IS NULL THEN NULL ELSE json_build_object(
+                        'user_id', forward_user_id,
+                        'chat_id', forward_chat_id,
+                        'message_id', forward_message_id,
+                        'name', forward_name
+                    ) END AS "forwarded_from: Json<ForwardOrigin>",
+                    (SELECT COUNT(*) FROM chat_messages AS replies WHERE replies.thread_root_id = chat_messages.id) AS "reply_count!",
+                    ARRAY(
+                        SELECT user_id FROM message_mentions WHERE message_mentions.message_id = chat_messages.id ORDER BY user_id
+                    ) AS "mentions!",
+                    (
+                        SELECT COALESCE(json_agg(json_build_object(
+                            'id', attachments.id,
+                            'file_name', attachments.file_name,
+                            'mime_type', attachments.mime_type,
+                            'size', attachments.size,
+                            'checksum', attachments.checksum
+                        ) ORDER BY message_attachments.position), '[]')
+                        FROM message_attachments
+                        JOIN attachments ON attachments.id = message_attachments.attachment_id
+                        WHERE message_attachments.message_id = chat_messages.id
+                    ) AS "attachments!: Json<Vec<AttachmentInfo>>",
+                    (
+                        SELECT COALESCE(json_agg(json_build_object(
+                            'emoji', summary.emoji,
+                            'count', summary.count,
+                            'reacted_by_me', summary.reacted_by_me
+                        ) ORDER BY summary.first_at), '[]')
+                        FROM (
+                            SELECT emoji, COUNT(*) AS count, BOOL_OR(message_reactions.user_id = $4) AS reacted_by_me, MIN(created_at) AS first_at
+                            FROM message_reactions
+                            WHERE message_reactions.message_id = chat_messages.id
+                            GROUP BY emoji
+                        ) AS summary
+                    ) AS "reactions!: Json<Vec<ReactionSummary>>",
+                    entities AS "entities: Json<Vec<MessageEntity>>",
+                    updated_at
+                FROM chat_messages
+                WHERE chat_id = $1 AND ($2::BIGINT IS NULL OR id < $2)
+                ORDER BY id DESC
+                LIMIT $3;
+            "#,
+            chat_id,
+            before_id,
+            limit,
+            viewer_id
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_messages) => Ok(_messages),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Messages with the given ids in any order, missing ones are skipped.
+    /// Reactions are marked for `viewer_id`.
+    pub async fn find_many(message_ids: &[MessageID], viewer_id: UserID, pool: &super::AppPool) -> Result<Vec<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            Message,
+            r#"
+                SELECT
+                    id, user_id, chat_id, content, payload AS "payload: Base64", epoch, sender_leaf,
+                    reply_to, thread_root_id,
+                    CASE WHEN reply_to IS NULL THEN NULL ELSE json_build_object(
+                        'message_id', reply_to,
+                        'user_id', quote_user_id,
+                        'content', quote_content
+                    ) END AS "quote: Json<Quote>",
+                    CASE WHEN forward_name IS NULL THEN NULL ELSE json_build_object(
+                        'user_id', forward_user_id,
+                        'chat_id', forward_chat_id,
+                        'message_id', forward_message_id,
+                        'name', forward_name
+                    ) END AS "forwarded_from: Json<ForwardOrigin>",
+                    (SELECT COUNT(*) FROM chat_messages AS replies WHERE replies.thread_root_id = chat_messages.id) AS "reply_count!",
+                    ARRAY(
+                        SELECT user_id FROM message_mentions WHERE message_mentions.message_id = chat_messages.id ORDER BY user_id
+                    ) AS "mentions!",
+                    (
+                        SELECT COALESCE(json_agg(json_build_object(
+                            'id', attachments.id,
+                            'file_name', attachments.file_name,
+                            'mime_type', attachments.mime_type,
+                            'size', attachments.size,
+                            'checksum', attachments.checksum
+                        ) ORDER BY message_attachments.position), '[]')
+                        FROM message_attachments
+                        JOIN attachments ON attachments.id = message_attachments.attachment_id
+                        WHERE message_attachments.message_id = chat_messages.id
+                    ) AS "attachments!: Json<Vec<AttachmentInfo>>",
+                    (
+                        SELECT COALESCE(json_agg(json_build_object(
+                            'emoji', summary.emoji,
+                            'count', summary.count,
+                            'reacted_by_me', summary.reacted_by_me
+                        ) ORDER BY summary.first_at), '[]')
+                        FROM (
+                            SELECT emoji, COUNT(*) AS count, BOOL_OR(message_reactions.user_id = $2) AS reacted_by_me, MIN(created_at) AS first_at
+                            FROM message_reactions
+                            WHERE message_reactions.message_id = chat_messages.id
+                            GROUP BY emoji
+                        ) AS summary
+                    ) AS "reactions!: Json<Vec<ReactionSummary>>",
+                    entities AS "entities: Json<Vec<MessageEntity>>",
+                    updated_at
+                FROM chat_messages
+                WHERE id = ANY($1);
+            "#,
+            message_ids,
+            viewer_id
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_messages) => Ok(_messages),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Messages mentioning the user in chats the user is still a member of, newest first
+    pub async fn mentioning(user_id: UserID, before_id: Option<MessageID>, limit: i64, pool: &super::AppPool) -> Result<Vec<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            Message,
+            r#"
+                SELECT
+                    id, user_id, chat_id, content, payload AS "payload: Base64", epoch, sender_leaf,
+                    reply_to, thread_root_id,
+                    CASE WHEN reply_to IS NULL THEN NULL ELSE json_build_object(
+                        'message_id', reply_to,
+                        'user_id', quote_user_id,
+                        'content', quote_content
+                    ) END AS "quote: Json<Quote>",
+                    CASE WHEN forward_name IS NULL THEN NULL ELSE json_build_object(
+                        'user_id', forward_user_id,
+                        'chat_id', forward_chat_id,
+                        'message_id', forward_message_id,
+                        'name', forward_name
+                    ) END AS "forwarded_from: Json<ForwardOrigin>",
+                    (SELECT COUNT(*) FROM chat_messages AS replies WHERE replies.thread_root_id = chat_messages.id) AS "reply_count!",
+                    ARRAY(
+                        SELECT user_id FROM message_mentions WHERE message_mentions.message_id = chat_messages.id ORDER BY user_id
+                    ) AS "mentions!",
+                    (
+                        SELECT COALESCE(json_agg(json_build_object(
+                            'id', attachments.id,
+                            'file_name', attachments.file_name,
+                            'mime_type', attachments.mime_type,
+                            'size', attachments.size,
+                            'checksum', attachments.checksum
+                        ) ORDER BY message_attachments.position), '[]')
+                        FROM message_attachments
+                        JOIN attachments ON attachments.id = message_attachments.attachment_id
+                        WHERE message_attachments.message_id = chat_messages.id
+                    ) AS "attachments!: Json<Vec<AttachmentInfo>>",
+                    (
+                        SELECT COALESCE(json_agg(json_build_object(
+                            'emoji', summary.emoji,
+                            'count', summary.count,
+                            'reacted_by_me', summary.reacted_by_me
+                        ) ORDER BY summary.first_at), '[]')
+                        FROM (
+                            SELECT emoji, COUNT(*) AS count, BOOL_OR(message_reactions.user_id = $1) AS reacted_by_me, MIN(created_at) AS first_at
+                            FROM message_reactions
+                            WHERE message_reactions.message_id = chat_messages.id
+                            GROUP BY emoji
+                        ) AS summary
+                    ) AS "reactions!: Json<Vec<ReactionSummary>>",
+                    entities AS "entities: Json<Vec<MessageEntity>>",
+                    updated_at
+                FROM chat_messages
+                WHERE id IN (
+                    SELECT message_id FROM message_mentions
+                    WHERE user_id = $1 AND ($2::BIGINT IS NULL OR message_id < $2)
+                )
+                    AND chat_id IN (SELECT chat_id FROM chat_users WHERE user_id = $1)
+                ORDER BY id DESC
+                LIMIT $3;
+            "#,
+            user_id,
+            before_id,
+            limit
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_messages) => Ok(_messages),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Replies of the thread sent after `after_id`, oldest first
+    pub async fn thread(root_id: MessageID, viewer_id: UserID, after_id: Option<MessageID>, limit: i64, pool: &super::AppPool) -> Result<Vec<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            Message,
+            r#"
+                SELECT
+                    id, user_id, chat_id, content, payload AS "payload: Base64", epoch, sender_leaf,
+                    reply_to, thread_root_id,
+                    CASE WHEN reply_to IS NULL THEN NULL ELSE json_build_object(
+                        'message_id', reply_to,
+                        'user_id', quote_user_id,
+                        'content', quote_content
+                    ) END AS "quote: Json<Quote>",
+                    CASE WHEN forward_name IS NULL THEN NULL ELSE json_build_object(
+                        'user_id', forward_user_id,
+                        'chat_id', forward_chat_id,
+                        'message_id', forward_message_id,
+                        'name', forward_name
+                    ) END AS "forwarded_from: Json<ForwardOrigin>",
+                    (SELECT COUNT(*) FROM chat_messages AS replies WHERE replies.thread_root_id = chat_messages.id) AS "reply_count!",
+                    ARRAY(
+                        SELECT user_id FROM message_mentions WHERE message_mentions.message_id = chat_messages.id ORDER BY user_id
+                    ) AS "mentions!",
+                    (
+                        SELECT COALESCE(json_agg(json_build_object(
+                            'id', attachments.id,
+                            'file_name', attachments.file_name,
+                            'mime_type', attachments.mime_type,
+                            'size', attachments.size,
+                            'checksum', attachments.checksum
+                        ) ORDER BY message_attachments.position), '[]')
+                        FROM message_attachments
+                        JOIN attachments ON attachments.id = message_attachments.attachment_id
+                        WHERE message_attachments.message_id = chat_messages.id
+                    ) AS "attachments!: Json<Vec<AttachmentInfo>>",
+                    (
+                        SELECT COALESCE(json_agg(json_build_object(
+                            'emoji', summary.emoji,
+                            'count', summary.count,
+                            'reacted_by_me', summary.reacted_by_me
+                        ) ORDER BY summary.first_at), '[]')
+                        FROM (
+                            SELECT emoji, COUNT(*) AS count, BOOL_OR(message_reactions.user_id = $4) AS reacted_by_me, MIN(created_at) AS first_at
+                            FROM message_reactions
+                            WHERE message_reactions.message_id = chat_messages.id
+                            GROUP BY emoji
+                        ) AS summary
+                    ) AS "reactions!: Json<Vec<ReactionSummary>>",
+                    entities AS "entities: Json<Vec<MessageEntity>>",
+                    updated_at
+                FROM chat_messages
+                WHERE thread_root_id = $1 AND ($2::BIGINT IS NULL OR id > $2)
+                ORDER BY id
+                LIMIT $3;
+            "#,
+            root_id,
+            after_id,
+            limit,
+            viewer_id
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_messages) => Ok(_messages),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NewMessage {
+    pub id: MessageID,
+    pub user_id: i64,
+    pub chat_id: i64,
+    pub content: String,
+    pub attachment_ids: Vec<AttachmentID>,
+    pub reply_to: Option<MessageID>,
+    pub thread_root_id: Option<MessageID>,
+    pub quote: Option<Quote>,
+    pub forwarded_from: Option<ForwardOrigin>,
+    pub mention_ids: Vec<UserID>,
+    pub entities: Vec<MessageEntity>,
+}
+
+impl NewMessage {
+    /// Inserts the message together with its attachment links
+    pub async fn insert(&self, pool: &super::AppPool) -> Result<Message, ModelError> {
+        Self::insert_batch(std::slice::from_ref(self), pool)
+            .await?
+            .pop()
+            .ok_or_else(|| ModelError::UnexpectedError("Internal server error".to_string()))
+    }
+
+    /// Inserts the messages with their attachment links in one transaction, either all of them are stored or none
+    pub async fn insert_batch(batch: &[Self], pool: &super::AppPool) -> Result<Vec<Message>, ModelError> {
+        let result: Result<(), sqlx::Error> = async {
+            let mut transaction = pool.begin().await?;
+            for message in batch {
+                sqlx::query!(
+                    r#"
+                        INSERT INTO
+                        chat_messages (
+                            id, user_id, chat_id, content, reply_to, thread_root_id, quote_user_id, quote_content,
+                            forward_user_id, forward_chat_id, forward_message_id, forward_name, entities
+                        )
+                        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13);
+                    "#,
+                    message.id,
+                    message.user_id,
+                    message.chat_id,
+                    message.content,
+                    message.reply_to,
+                    message.thread_root_id,
+                    message.quote.as_ref().map(|quote| quote.user_id),
+                    message.quote.as_ref().and_then(|quote| quote.content.clone()),
+                    message.forwarded_from.as_ref().and_then(|origin| origin.user_id),
+                    message.forwarded_from.as_ref().and_then(|origin| origin.chat_id),
+                    message.forwarded_from.as_ref().and_then(|origin| origin.message_id),
+                    message.forwarded_from.as_ref().map(|origin| origin.name.clone()),
+                    Json(&message.entities) as _
+                )
+                .execute(&mut *transaction)
+                .await?;
+
+                sqlx::query!(
+                    r#"
+                        INSERT INTO message_attachments (message_id, attachment_id, position)
+                        SELECT $1, attachment_id, position - 1
+                        FROM UNNEST($2::BIGINT[]) WITH ORDINALITY AS t(attachment_id, position);
+                    "#,
+                    message.id,
+                    &message.attachment_ids
+                )
+                .execute(&mut *transaction)
+                .await?;
+
+                sqlx::query!(
+                    r#"
+                        INSERT INTO message_mentions (message_id, user_id)
+                        SELECT $1, UNNEST($2::BIGINT[]);
+                    "#,
+                    message.id,
+                    &message.mention_ids
+                )
+                .execute(&mut *transaction)
+                .await?;
+            }
+            transaction.commit().await
+        }.await;
+
+        match result {
+            Ok(_) => {
+                let mut messages = Vec::with_capacity(batch.len());
+                for message in batch {
+                    let message = Message::find(message.id, pool)
+                        .await?
+                        .ok_or_else(|| ModelError::UnexpectedError("Internal server error".to_string()))?;
+                    messages.push(message);
+                }
+                Ok(messages)
+            }
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NewEncryptedMessage {
+    pub id: MessageID,
     pub user_id: i64,
     pub chat_id: i64,
-    pub content: String
+    pub payload: Vec<u8>,
+    pub epoch: i64,
+    pub sender_leaf: i32,
+}
+
+impl NewEncryptedMessage {
+    pub async fn insert(&self, pool: &super::AppPool) -> Result<Message, ModelError> {
+        let result = sqlx::query_as!(
+            Message,
+            r#"
+                INSERT INTO
+                chat_messages (id, user_id, chat_id, payload, epoch, sender_leaf)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                RETURNING
+                    id, user_id, chat_id, content, payload AS "payload: Base64", epoch, sender_leaf,
+                    reply_to, thread_root_id,
+                    NULL::JSON AS "quote: Json<Quote>",
+                    NULL::JSON AS "forwarded_from: Json<ForwardOrigin>",
+                    0::BIGINT AS "reply_count!",
+                    ARRAY[]::BIGINT[] AS "mentions!",
+                    '[]'::JSON AS "attachments!: Json<Vec<AttachmentInfo>>",
+                    '[]'::JSON AS "reactions!: Json<Vec<ReactionSummary>>",
+                    entities AS "entities: Json<Vec<MessageEntity>>",
+                    updated_at;
+            "#,
+            self.id,
+            self.user_id,
+            self.chat_id,
+            self.payload,
+            self.epoch,
+            self.sender_leaf
+        )
+        .fetch_one(pool)
+        .await;
+
+        match result {
+            Ok(_message) => Ok(_message),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
 }
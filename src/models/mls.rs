@@ -0,0 +1,423 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::postgres::PgDatabaseError;
+
+use crate::{
+    core::Base64,
+    models::{chat::ChatID, device::DeviceID, errors::ModelError, user::UserID}
+};
+
+
+// `ContentType` values of RFC 9420
+pub const CONTENT_TYPE_APPLICATION: i16 = 1;
+pub const CONTENT_TYPE_PROPOSAL: i16 = 2;
+pub const CONTENT_TYPE_COMMIT: i16 = 3;
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct MlsGroup {
+    pub group_id: Vec<u8>,
+    pub chat_id: ChatID,
+    pub epoch: i64,
+    pub ciphersuite: i32,
+}
+
+impl MlsGroup {
+    pub async fn find(group_id: &[u8], pool: &super::AppPool) -> Result<Option<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            MlsGroup,
+            "SELECT group_id, chat_id, epoch, ciphersuite FROM mls_groups WHERE group_id = $1",
+            group_id
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match result {
+            Ok(_group) => Ok(_group),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    pub async fn find_by_chat(chat_id: ChatID, pool: &super::AppPool) -> Result<Option<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            MlsGroup,
+            "SELECT group_id, chat_id, epoch, ciphersuite FROM mls_groups WHERE chat_id = $1",
+            chat_id
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match result {
+            Ok(_group) => Ok(_group),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NewMlsGroup {
+    pub group_id: Vec<u8>,
+    pub chat_id: ChatID,
+    pub ciphersuite: i32,
+}
+
+impl NewMlsGroup {
+    pub async fn insert(&self, pool: &super::AppPool) -> Result<(), ModelError> {
+        let result = sqlx::query!(
+            "INSERT INTO mls_groups (group_id, chat_id, ciphersuite) VALUES ($1, $2, $3)",
+            self.group_id,
+            self.chat_id,
+            self.ciphersuite
+        )
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(sqlx::Error::Database(db_err)) => {
+                if let Some(pg_err) = db_err.try_downcast_ref::<PgDatabaseError>() && pg_err.code() == "23505" {
+                    return Err(ModelError::ClientError("The chat or the group id already has an MLS group".to_string()));
+                }
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct MlsMessage {
+    pub id: i64,
+    pub chat_id: ChatID,
+    pub sender_id: UserID,
+    pub epoch: i64,
+    pub content_type: i16,
+    pub data: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// `MlsMessage` as it is sent to clients
+#[derive(Debug, Clone, Serialize)]
+pub struct MlsMessageDTO {
+    pub id: i64,
+    pub chat_id: ChatID,
+    pub sender_id: UserID,
+    pub epoch: i64,
+    pub content_type: i16,
+    pub data: Base64,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<MlsMessage> for MlsMessageDTO {
+    fn from(message: MlsMessage) -> Self {
+        Self {
+            id: message.id,
+            chat_id: message.chat_id,
+            sender_id: message.sender_id,
+            epoch: message.epoch,
+            content_type: message.content_type,
+            data: Base64(message.data),
+            created_at: message.created_at,
+        }
+    }
+}
+
+impl MlsMessage {
+    pub async fn find(message_id: i64, pool: &super::AppPool) -> Result<Option<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            MlsMessage,
+            "SELECT id, chat_id, sender_id, epoch, content_type, data, created_at FROM mls_messages WHERE id = $1",
+            message_id
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match result {
+            Ok(_message) => Ok(_message),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Messages of the chat accepted after `after_id`, oldest first
+    pub async fn list(chat_id: ChatID, after_id: i64, limit: i64, pool: &super::AppPool) -> Result<Vec<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            MlsMessage,
+            r#"
+                SELECT id, chat_id, sender_id, epoch, content_type, data, created_at
+                FROM mls_messages
+                WHERE chat_id = $1 AND id > $2
+                ORDER BY id
+                LIMIT $3;
+            "#,
+            chat_id,
+            after_id,
+            limit
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_messages) => Ok(_messages),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NewMlsMessage {
+    pub id: i64,
+    pub group_id: Vec<u8>,
+    pub sender_id: UserID,
+    pub epoch: i64,
+    pub content_type: i16,
+    pub data: Vec<u8>,
+}
+
+impl NewMlsMessage {
+    /// Stores the message if its epoch fits the group.
+    /// Commits must be made for the current epoch and advance it, so only the first
+    /// Commit of an epoch is accepted and all members apply Commits in the same order.
+    pub async fn insert(&self, pool: &super::AppPool) -> Result<MlsMessage, ModelError> {
+        let result: Result<Result<MlsMessage, String>, sqlx::Error> = async {
+            let mut transaction = pool.begin().await?;
+
+            // Locks the group, so Commits of one group are accepted one at a time
+            let group = sqlx::query_as!(
+                MlsGroup,
+                "SELECT group_id, chat_id, epoch, ciphersuite FROM mls_groups WHERE group_id = $1 FOR UPDATE",
+                self.group_id
+            )
+            .fetch_optional(&mut *transaction)
+            .await?;
+
+            let Some(group) = group else {
+                return Ok(Err("MLS group not found".to_string()))
+            };
+            if self.epoch > group.epoch {
+                return Ok(Err(format!("Epoch {} is ahead of the group epoch {}", self.epoch, group.epoch)))
+            }
+            if self.content_type != CONTENT_TYPE_APPLICATION && self.epoch < group.epoch {
+                return Ok(Err(format!("Epoch {} is stale, the group is at epoch {}", self.epoch, group.epoch)))
+            }
+
+            let message = sqlx::query_as!(
+                MlsMessage,
+                r#"
+                    INSERT INTO
+                    mls_messages (id, group_id, chat_id, sender_id, epoch, content_type, data)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7)
+                    RETURNING id, chat_id, sender_id, epoch, content_type, data, created_at;
+                "#,
+                self.id,
+                self.group_id,
+                group.chat_id,
+                self.sender_id,
+                self.epoch,
+                self.content_type,
+                self.data
+            )
+            .fetch_one(&mut *transaction)
+            .await?;
+
+            if self.content_type == CONTENT_TYPE_COMMIT {
+                sqlx::query!(
+                    "UPDATE mls_groups SET epoch = epoch + 1 WHERE group_id = $1",
+                    self.group_id
+                )
+                .execute(&mut *transaction)
+                .await?;
+            }
+
+            transaction.commit().await?;
+            Ok(Ok(message))
+        }.await;
+
+        match result {
+            Ok(Ok(_message)) => Ok(_message),
+            Ok(Err(error)) => Err(ModelError::ClientError(error)),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct MlsWelcome {
+    pub id: i64,
+    pub key_package_ref: Vec<u8>,
+    pub device_id: DeviceID,
+    pub chat_id: ChatID,
+    pub sender_id: UserID,
+    pub data: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// `MlsWelcome` as it is sent to clients
+#[derive(Debug, Clone, Serialize)]
+pub struct MlsWelcomeDTO {
+    pub id: i64,
+    pub key_package_ref: Base64,
+    pub device_id: DeviceID,
+    pub chat_id: ChatID,
+    pub sender_id: UserID,
+    pub data: Base64,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<MlsWelcome> for MlsWelcomeDTO {
+    fn from(welcome: MlsWelcome) -> Self {
+        Self {
+            id: welcome.id,
+            key_package_ref: Base64(welcome.key_package_ref),
+            device_id: welcome.device_id,
+            chat_id: welcome.chat_id,
+            sender_id: welcome.sender_id,
+            data: Base64(welcome.data),
+            created_at: welcome.created_at,
+        }
+    }
+}
+
+impl MlsWelcome {
+    pub async fn find(welcome_id: i64, pool: &super::AppPool) -> Result<Option<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            MlsWelcome,
+            "SELECT id, key_package_ref, device_id, chat_id, sender_id, data, created_at FROM mls_welcomes WHERE id = $1",
+            welcome_id
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match result {
+            Ok(_welcome) => Ok(_welcome),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Welcomes waiting in the mailbox of the device
+    pub async fn pending(user_id: UserID, device_id: DeviceID, pool: &super::AppPool) -> Result<Vec<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            MlsWelcome,
+            r#"
+                SELECT id, key_package_ref, device_id, chat_id, sender_id, data, created_at
+                FROM mls_welcomes
+                WHERE user_id = $1 AND device_id = $2
+                ORDER BY id;
+            "#,
+            user_id,
+            device_id
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_welcomes) => Ok(_welcomes),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Removes the acknowledged Welcome, returns `false` if the device has no such Welcome
+    pub async fn acknowledge(welcome_id: i64, user_id: UserID, device_id: DeviceID, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result = sqlx::query!(
+            "DELETE FROM mls_welcomes WHERE id = $1 AND user_id = $2 AND device_id = $3",
+            welcome_id,
+            user_id,
+            device_id
+        )
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(_result) => Ok(_result.rows_affected() == 1),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NewMlsWelcome {
+    pub id: i64,
+    pub key_package_ref: Vec<u8>,
+    pub user_id: UserID,
+    pub device_id: DeviceID,
+    pub chat_id: ChatID,
+    pub sender_id: UserID,
+    pub data: Vec<u8>,
+}
+
+impl NewMlsWelcome {
+    /// Stores the Welcomes and makes their recipients members of the chat in one transaction.
+    /// The caller checks the recipients against the block policy and the member limit
+    pub async fn insert_batch(batch: &[Self], pool: &super::AppPool) -> Result<(), ModelError> {
+        let result: Result<(), sqlx::Error> = async {
+            let mut transaction = pool.begin().await?;
+            for welcome in batch {
+                sqlx::query!(
+                    r#"
+                        INSERT INTO
+                        mls_welcomes (id, key_package_ref, user_id, device_id, chat_id, sender_id, data)
+                        VALUES ($1, $2, $3, $4, $5, $6, $7);
+                    "#,
+                    welcome.id,
+                    welcome.key_package_ref,
+                    welcome.user_id,
+                    welcome.device_id,
+                    welcome.chat_id,
+                    welcome.sender_id,
+                    welcome.data
+                )
+                .execute(&mut *transaction)
+                .await?;
+
+                sqlx::query!(
+                    "INSERT INTO chat_users (chat_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                    welcome.chat_id,
+                    welcome.user_id
+                )
+                .execute(&mut *transaction)
+                .await?;
+            }
+            transaction.commit().await
+        }.await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(sqlx::Error::Database(db_err)) => {
+                if let Some(pg_err) = db_err.try_downcast_ref::<PgDatabaseError>() && pg_err.code() == "23505" {
+                    return Err(ModelError::ClientError("A Welcome for this KeyPackage has already been sent".to_string()));
+                }
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
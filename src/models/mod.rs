@@ -3,6 +3,14 @@ use sqlx::{Pool, Postgres};
 pub mod user;
 pub mod chat;
 pub mod message;
+pub mod attachment;
+pub mod reaction;
+pub mod pin;
+pub mod block;
+pub mod device;
+pub mod key_package;
+pub mod mls;
+pub mod worker;
 
 
 pub type AppPool = Pool<Postgres>;
@@ -24,35 +32,32 @@ pub mod prelude {
         AtomicSnowflakeGenerator,
         SnowflakeGeneratorAsyncTokioExt
     };
-    use std::collections::hash_map::DefaultHasher;
     use std::fmt::Debug;
-    use std::hash::{Hash, Hasher};
+
+    use super::worker::LeaseValidity;
 
     type SnowflakeGeneratorType = AtomicSnowflakeGenerator<SnowflakeDiscordId, MonotonicClock>;
 
     pub struct SnowflakeGenerator {
         generator: SnowflakeGeneratorType,
-        machine_id: u64
+        machine_id: u64,
+        lease: LeaseValidity
     }
 
     impl SnowflakeGenerator {
-        pub fn new() ->  Self {
+        /// `machine_id` must be unique among running instances, see `WorkerLease`
+        pub fn new(machine_id: u64, lease: LeaseValidity) ->  Self {
             let clock = MonotonicClock::with_epoch(UNIX_EPOCH);
-            let machine_id = Self::get_machine_id();
             Self {
                 generator: AtomicSnowflakeGenerator::new( machine_id.clone(), clock ),
-                machine_id: machine_id
+                machine_id: machine_id,
+                lease
             }
         }
 
-        fn get_machine_id() -> u64 {
-            let machine_id: String = machine_uid::get().unwrap();
-            let mut hasher = DefaultHasher::new();
-            machine_id.hash(&mut hasher);
-            hasher.finish() & 0b1111111111
-        }
-
         pub async fn generate_id(&self) -> i64 {
+            // Another instance may generate ids with the same machine id once the lease has expired
+            assert!(self.lease.is_valid(), "Snowflake worker id lease has expired, no more ids are generated");
             self.generator.try_next_id_async().await.unwrap().to_raw() as i64
         }
     }
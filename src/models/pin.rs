@@ -0,0 +1,109 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::models::{chat::ChatID, errors::ModelError, message::MessageID, user::UserID};
+
+
+#[derive(Debug, Clone, sqlx::FromRow, Serialize)]
+pub struct Pin {
+    pub chat_id: ChatID,
+    pub message_id: MessageID,
+    pub pinned_by: UserID,
+    pub pinned_at: DateTime<Utc>,
+}
+
+impl Pin {
+    /// Pins of the chat, the latest first
+    pub async fn list(chat_id: ChatID, pool: &super::AppPool) -> Result<Vec<Self>, ModelError> {
+        let result = sqlx::query_as!(
+            Pin,
+            r#"
+                SELECT chat_id, message_id, pinned_by, pinned_at
+                FROM pinned_messages
+                WHERE chat_id = $1
+                ORDER BY pinned_at DESC;
+            "#,
+            chat_id
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_pins) => Ok(_pins),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Pins the message unless the chat already has `max_pins` pins.
+    /// Returns `false` if the message is already pinned.
+    pub async fn insert(chat_id: ChatID, message_id: MessageID, pinned_by: UserID, max_pins: i64, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result: Result<Option<bool>, sqlx::Error> = async {
+            let mut transaction = pool.begin().await?;
+            // Serializes pins of the chat, so the cap holds under concurrent requests
+            sqlx::query!("SELECT id FROM chats WHERE id = $1 FOR UPDATE", chat_id)
+                .fetch_optional(&mut *transaction)
+                .await?;
+
+            let (pinned, count) = sqlx::query!(
+                r#"
+                    SELECT
+                        EXISTS(SELECT 1 FROM pinned_messages WHERE chat_id = $1 AND message_id = $2) AS "pinned!",
+                        (SELECT COUNT(*) FROM pinned_messages WHERE chat_id = $1) AS "count!";
+                "#,
+                chat_id,
+                message_id
+            )
+            .fetch_one(&mut *transaction)
+            .await
+            .map(|row| (row.pinned, row.count))?;
+            if pinned {
+                return Ok(Some(false))
+            }
+            if count >= max_pins {
+                return Ok(None)
+            }
+
+            sqlx::query!(
+                "INSERT INTO pinned_messages (chat_id, message_id, pinned_by) VALUES ($1, $2, $3)",
+                chat_id,
+                message_id,
+                pinned_by
+            )
+            .execute(&mut *transaction)
+            .await?;
+            transaction.commit().await?;
+            Ok(Some(true))
+        }.await;
+
+        match result {
+            Ok(Some(_inserted)) => Ok(_inserted),
+            Ok(None) => Err(ModelError::ClientError(format!("A chat can not have more than {} pinned messages", max_pins))),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Returns `false` if the message is not pinned
+    pub async fn delete(chat_id: ChatID, message_id: MessageID, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result = sqlx::query!(
+            "DELETE FROM pinned_messages WHERE chat_id = $1 AND message_id = $2",
+            chat_id,
+            message_id
+        )
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(_result) => Ok(_result.rows_affected() == 1),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::{errors::ModelError, message::MessageID, user::UserID};
+
+
+/// Reactions with one emoji on a message, as seen by the user who loads the message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReactionSummary {
+    pub emoji: String,
+    pub count: i64,
+    pub reacted_by_me: bool,
+}
+
+#[derive(Debug)]
+pub struct Reaction {
+    pub message_id: MessageID,
+    pub user_id: UserID,
+    pub emoji: String,
+}
+
+impl Reaction {
+    /// Adds the reaction unless the message already has `max_distinct` other emojis.
+    /// Returns `false` if the user has already reacted with the emoji.
+    pub async fn insert(&self, max_distinct: i64, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result: Result<Option<bool>, sqlx::Error> = async {
+            let mut transaction = pool.begin().await?;
+            // Serializes reactions on the message, so the cap holds under concurrent requests
+            sqlx::query!("SELECT id FROM chat_messages WHERE id = $1 FOR UPDATE", self.message_id)
+                .fetch_optional(&mut *transaction)
+                .await?;
+
+            let allowed = sqlx::query_scalar!(
+                r#"
+                    SELECT
+                        EXISTS(SELECT 1 FROM message_reactions WHERE message_id = $1 AND emoji = $2)
+                        OR COUNT(DISTINCT emoji) < $3
+                        AS "allowed!"
+                    FROM message_reactions
+                    WHERE message_id = $1;
+                "#,
+                self.message_id,
+                self.emoji,
+                max_distinct
+            )
+            .fetch_one(&mut *transaction)
+            .await?;
+            if !allowed {
+                return Ok(None)
+            }
+
+            let inserted = sqlx::query!(
+                r#"
+                    INSERT INTO message_reactions (message_id, user_id, emoji)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT DO NOTHING;
+                "#,
+                self.message_id,
+                self.user_id,
+                self.emoji
+            )
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected() == 1;
+
+            transaction.commit().await?;
+            Ok(Some(inserted))
+        }.await;
+
+        match result {
+            Ok(Some(_inserted)) => Ok(_inserted),
+            Ok(None) => Err(ModelError::ClientError(format!("A message can not have more than {} different reactions", max_distinct))),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Returns `false` if the user has not reacted with the emoji
+    pub async fn delete(&self, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result = sqlx::query!(
+            "DELETE FROM message_reactions WHERE message_id = $1 AND user_id = $2 AND emoji = $3",
+            self.message_id,
+            self.user_id,
+            self.emoji
+        )
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(_result) => Ok(_result.rows_affected() == 1),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
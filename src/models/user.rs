@@ -6,7 +6,7 @@ use crate::models::errors::ModelError;
 
 pub type UserID = i64;
 
-#[derive(Debug, Clone, sqlx::FromRow, Serialize)]
+#[derive(Debug, Clone, sqlx::FromRow)]
 pub struct User {
     pub id: UserID,
     pub username: String,
@@ -15,7 +15,33 @@ pub struct User {
     pub status: Option<String>,
     pub bio: Option<String>,
     pub is_banned: bool,
-    pub is_active: bool
+    pub is_active: bool,
+    pub discoverable: bool,
+    pub avatar: Option<String>,
+    /// Forwarded copies of the user's messages link back to the user and the chat
+    pub link_forwards: bool,
+}
+
+/// Part of the user shown to other users
+#[derive(Debug, Clone, sqlx::FromRow, Serialize)]
+pub struct UserProfile {
+    pub id: UserID,
+    pub username: String,
+    pub name: String,
+    pub status: Option<String>,
+    pub bio: Option<String>,
+    /// Content hash of the avatar
+    pub avatar: Option<String>,
+}
+
+/// Profile fields to change, `Some(None)` clears an optional field
+#[derive(Debug, Default)]
+pub struct ProfileUpdate {
+    pub name: Option<String>,
+    pub status: Option<Option<String>>,
+    pub bio: Option<Option<String>>,
+    pub discoverable: Option<bool>,
+    pub link_forwards: Option<bool>,
 }
 
 #[derive(Debug, Clone, sqlx::FromRow)]
@@ -43,6 +69,155 @@ impl User {
         }
     }
 
+    /// Profile of an active user
+    pub async fn find_profile(user_id: UserID, pool: &super::AppPool) -> Result<Option<UserProfile>, ModelError> {
+        let result = sqlx::query_as!(
+            UserProfile,
+            "SELECT id, username, name, status, bio, avatar FROM users WHERE id = $1 AND is_active AND NOT is_banned",
+            user_id
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match result {
+            Ok(_profile) => Ok(_profile),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    pub async fn update_profile(user_id: UserID, update: &ProfileUpdate, pool: &super::AppPool) -> Result<Option<UserProfile>, ModelError> {
+        let result = sqlx::query_as!(
+            UserProfile,
+            r#"
+                UPDATE users SET
+                    name = COALESCE($2, name),
+                    status = CASE WHEN $3 THEN $4 ELSE status END,
+                    bio = CASE WHEN $5 THEN $6 ELSE bio END,
+                    discoverable = COALESCE($7, discoverable),
+                    link_forwards = COALESCE($8, link_forwards)
+                WHERE id = $1 AND is_active
+                RETURNING id, username, name, status, bio, avatar;
+            "#,
+            user_id,
+            update.name,
+            update.status.is_some(),
+            update.status.clone().flatten(),
+            update.bio.is_some(),
+            update.bio.clone().flatten(),
+            update.discoverable,
+            update.link_forwards
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match result {
+            Ok(_profile) => Ok(_profile),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    pub async fn update_avatar(user_id: UserID, avatar: Option<String>, pool: &super::AppPool) -> Result<Option<UserProfile>, ModelError> {
+        let result = sqlx::query_as!(
+            UserProfile,
+            r#"
+                UPDATE users SET avatar = $2
+                WHERE id = $1 AND is_active
+                RETURNING id, username, name, status, bio, avatar;
+            "#,
+            user_id,
+            avatar
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match result {
+            Ok(_profile) => Ok(_profile),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Discoverable users whose username starts with the query or whose username or name is similar to it.
+    /// Prefix matches come first, then the most similar ones.
+    pub async fn search(query: &str, searcher_id: UserID, excluded_ids: &[UserID], offset: i64, limit: i64, pool: &super::AppPool) -> Result<Vec<UserProfile>, ModelError> {
+        let prefix = format!("{}%", query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+        let result = sqlx::query_as!(
+            UserProfile,
+            r#"
+                SELECT id, username, name, status, bio, avatar
+                FROM users
+                WHERE is_active AND NOT is_banned AND discoverable AND id <> $3 AND id <> ALL($6)
+                    AND (username ILIKE $2 OR username % $1 OR name % $1)
+                ORDER BY
+                    username ILIKE $2 DESC,
+                    GREATEST(similarity(username, $1), similarity(name, $1)) DESC,
+                    id
+                OFFSET $4
+                LIMIT $5;
+            "#,
+            query,
+            prefix,
+            searcher_id,
+            offset,
+            limit,
+            excluded_ids
+        )
+        .fetch_all(pool)
+        .await;
+
+        match result {
+            Ok(_profiles) => Ok(_profiles),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    pub async fn find_password_hash(user_id: UserID, pool: &super::AppPool) -> Result<Option<UserAthorizeDTO>, ModelError> {
+        let dto = sqlx::query_as!(
+            UserAthorizeDTO,
+            "SELECT id, hashed_password FROM users WHERE id = $1",
+            user_id
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match dto {
+            Ok(_dto) => Ok(_dto),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    pub async fn update_password(user_id: UserID, hashed_password: String, pool: &super::AppPool) -> Result<(), ModelError> {
+        let result = sqlx::query!(
+            "UPDATE users SET hashed_password = $2 WHERE id = $1",
+            user_id,
+            hashed_password
+        )
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
     pub async fn authorize(username: String, pool: &super::AppPool) -> Result<Option<UserAthorizeDTO>, ModelError> {
         let dto = sqlx::query_as!(
             UserAthorizeDTO,
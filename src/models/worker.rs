@@ -0,0 +1,142 @@
+use std::{
+    process,
+    sync::{atomic::{AtomicU64, Ordering}, Arc},
+    time::Duration
+};
+
+use tokio::time::Instant;
+
+use crate::{models::errors::ModelError, settings::{SNOWFLAKE_HEARTBEAT_INTERVAL, SNOWFLAKE_LEASE_LIFETIME}};
+
+
+pub type WorkerID = i32;
+
+/// Snowflake worker id leased from the `snowflake_workers` table,
+/// so several instances never generate ids with the same worker id.
+#[derive(Debug)]
+pub struct WorkerLease {
+    pub worker_id: WorkerID,
+    instance: String,
+    validity: LeaseValidity,
+}
+
+/// Until when the lease is surely held by this instance, shared with the snowflake generator
+#[derive(Debug, Clone)]
+pub struct LeaseValidity {
+    origin: Instant,
+    /// Milliseconds after `origin`
+    valid_until: Arc<AtomicU64>,
+}
+
+impl LeaseValidity {
+    fn new(acquired_at: Instant) -> Self {
+        let validity = Self { origin: acquired_at, valid_until: Arc::new(AtomicU64::new(0)) };
+        validity.renew(acquired_at);
+        validity
+    }
+
+    /// The lease runs from the time the renewal was sent, a second is left for clock drift
+    fn renew(&self, sent_at: Instant) {
+        let valid_until = (sent_at + *SNOWFLAKE_LEASE_LIFETIME).saturating_duration_since(self.origin + Duration::from_secs(1));
+        self.valid_until.fetch_max(valid_until.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn is_valid(&self) -> bool {
+        (self.origin.elapsed().as_millis() as u64) < self.valid_until.load(Ordering::Relaxed)
+    }
+}
+
+impl WorkerLease {
+    /// Leases the `requested` worker id or the lowest free one,
+    /// returns `None` when the requested id or all of them are taken
+    pub async fn acquire(requested: Option<WorkerID>, pool: &super::AppPool) -> Result<Option<Self>, ModelError> {
+        let instance = format!("{}-{}", machine_uid::get().unwrap_or_default(), process::id());
+        let sent_at = Instant::now();
+        let result = sqlx::query_scalar!(
+            r#"
+                UPDATE snowflake_workers
+                SET instance = $1, heartbeat_at = NOW()
+                WHERE worker_id = (
+                    SELECT worker_id FROM snowflake_workers
+                    WHERE heartbeat_at < NOW() - make_interval(secs => $2)
+                        AND ($3::INTEGER IS NULL OR worker_id = $3)
+                    ORDER BY worker_id
+                    LIMIT 1
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING worker_id;
+            "#,
+            instance,
+            SNOWFLAKE_LEASE_LIFETIME.as_secs_f64(),
+            requested
+        )
+        .fetch_optional(pool)
+        .await;
+
+        match result {
+            Ok(worker_id) => Ok(worker_id.map(|_worker_id| Self { worker_id: _worker_id, instance, validity: LeaseValidity::new(sent_at) })),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    /// Prolongs the lease, returns `false` when another instance has taken it over
+    pub async fn heartbeat(&self, pool: &super::AppPool) -> Result<bool, ModelError> {
+        let result = sqlx::query!(
+            r#"
+                UPDATE snowflake_workers
+                SET instance = $2, heartbeat_at = NOW()
+                WHERE worker_id = $1
+                AND (instance = $2 OR heartbeat_at < NOW() - make_interval(secs => $3));
+            "#,
+            self.worker_id,
+            self.instance,
+            SNOWFLAKE_LEASE_LIFETIME.as_secs_f64()
+        )
+        .execute(pool)
+        .await;
+
+        match result {
+            Ok(_result) => Ok(_result.rows_affected() == 1),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    pub fn validity(&self) -> LeaseValidity {
+        self.validity.clone()
+    }
+
+    /// Keeps the lease alive. Generating ids with a lost lease could collide with
+    /// another instance, so the process exits instead.
+    pub fn spawn_heartbeat(self, pool: super::AppPool) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SNOWFLAKE_HEARTBEAT_INTERVAL);
+            // The first tick completes immediately, the lease has just been acquired
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let sent_at = Instant::now();
+                // A hanging connection must not hold the loop past the end of the lease
+                match tokio::time::timeout(SNOWFLAKE_HEARTBEAT_INTERVAL, self.heartbeat(&pool)).await {
+                    Ok(Ok(true)) => self.validity.renew(sent_at),
+                    Ok(Ok(false)) => {
+                        tracing::error!("Snowflake worker id `{}` has been taken over by another instance", self.worker_id);
+                        process::exit(1);
+                    }
+                    Ok(Err(_)) => {}
+                    Err(_) => tracing::error!("Snowflake worker id `{}` heartbeat has timed out", self.worker_id),
+                }
+                // The lease may already be expired if the database was unreachable for too long
+                if !self.validity.is_valid() {
+                    tracing::error!("Snowflake worker id `{}` lease has expired", self.worker_id);
+                    process::exit(1);
+                }
+            }
+        });
+    }
+}
@@ -1,15 +1,173 @@
-use std::{env, time::Duration};
+use std::{env, str::FromStr, sync::LazyLock, time::Duration};
 
+use crate::{bus::bus::EventBusKind, cache::cache::CacheBackendKind, storage::storage::StorageBackendKind};
+
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+// Unlike `env_or`, a value which can not be parsed is an error rather than the default
+fn env_parse_or<T: FromStr>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Ok(value) => value.parse()
+            .unwrap_or_else(|_| panic!("`{}` has invalid value `{}`", key, value)),
+        Err(_) => default,
+    }
+}
+
+// Comma separated list, numbers may be written in hex with the `0x` prefix
+fn env_u16_list(key: &str, default: &[u16]) -> Vec<u16> {
+    let Ok(value) = env::var(key) else {
+        return default.to_vec()
+    };
+    value.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| match item.strip_prefix("0x") {
+            Some(hex) => u16::from_str_radix(hex, 16),
+            None => item.parse(),
+        }.unwrap_or_else(|_| panic!("`{}` contains invalid number `{}`", key, item)))
+        .collect()
+}
 
 
 // Cache
 pub const DEFAULT_RECORD_LIFETIME: Duration = Duration::from_secs(600);
+pub const CACHE_PURGE_INTERVAL: Duration = Duration::from_secs(60);
+// `memory` (single instance only), `postgres` or `resp`
+pub static CACHE_BACKEND: LazyLock<CacheBackendKind> = LazyLock::new(|| env_parse_or("CACHE_BACKEND", CacheBackendKind::Memory));
+pub static CACHE_URL: LazyLock<String> = LazyLock::new(|| env_or("CACHE_URL", "redis://127.0.0.1:6379".to_string()));
+// Failing to connect at startup is fatal, so attempts are bounded
+pub const CACHE_CONNECT_RETRIES: usize = 3;
+pub const CACHE_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+pub const CACHE_RECONNECT_MAX_DELAY: Duration = Duration::from_secs(1);
 
 
 // Ticket
 pub const TICKET_LENGTH: usize = 32;
 pub const TICKET_LIFETIME: usize = 5 * 5 * 60;// seconds
 
+// WebSocket tickets are single-use, so they only have to live long enough for the upgrade request
+pub static WS_TICKET_LIFETIME: LazyLock<Duration> = LazyLock::new(|| {
+    Duration::from_secs(env_or("WS_TICKET_LIFETIME", 30))
+});
+pub static WS_TICKET_MAX_PER_USER: LazyLock<usize> = LazyLock::new(|| env_or("WS_TICKET_MAX_PER_USER", 5));
+pub static WS_TICKET_BIND_IP: LazyLock<bool> = LazyLock::new(|| env_or("WS_TICKET_BIND_IP", true));
+pub static WS_TICKET_BIND_USER_AGENT: LazyLock<bool> = LazyLock::new(|| env_or("WS_TICKET_BIND_USER_AGENT", true));
+
+
+// Storage
+// Only `local` for now
+pub static STORAGE_BACKEND: LazyLock<StorageBackendKind> = LazyLock::new(|| env_parse_or("STORAGE_BACKEND", StorageBackendKind::Local));
+pub static STORAGE_PATH: LazyLock<String> = LazyLock::new(|| env_or("STORAGE_PATH", "./storage".to_string()));
+
+
+// Event bus
+// `in_process` (single instance only) or `postgres`
+pub static EVENT_BUS: LazyLock<EventBusKind> = LazyLock::new(|| env_parse_or("EVENT_BUS", EventBusKind::InProcess));
+pub const EVENT_BUS_CHANNEL: &str = "chatree_events";
+pub const EVENT_BUS_CAPACITY: usize = 1024;
+pub const EVENT_BUS_RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+
+// WebSocket
+pub const CLIENT_CHANNEL_CAPACITY: usize = 100;
+
+
+// Snowflake
+pub const SNOWFLAKE_MAX_WORKER_ID: u64 = 1023;
+// Fixed worker id, when it is not set the id is leased from the database
+pub static SNOWFLAKE_WORKER_ID: LazyLock<Option<u64>> = LazyLock::new(|| {
+    env::var("SNOWFLAKE_WORKER_ID").ok().map(|value| value.parse().expect("`SNOWFLAKE_WORKER_ID` must be a number"))
+});
+pub static SNOWFLAKE_LEASE_LIFETIME: LazyLock<Duration> = LazyLock::new(|| {
+    Duration::from_secs(env_or("SNOWFLAKE_LEASE_LIFETIME", 60))
+});
+pub const SNOWFLAKE_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+pub const SNOWFLAKE_MIN_HEARTBEATS_PER_LEASE: u32 = 3;
+
+
+// Profile
+pub const PROFILE_NAME_MAX_LENGTH: usize = 64;
+pub const PROFILE_STATUS_MAX_LENGTH: usize = 140;
+pub const PROFILE_BIO_MAX_LENGTH: usize = 500;
+// Shorter queries match too many users and can not use the trigram indexes
+pub const USER_SEARCH_MIN_QUERY_LENGTH: usize = 3;
+pub const USER_SEARCH_MAX_QUERY_LENGTH: usize = 64;
+pub const USER_SEARCH_PAGE_SIZE: i64 = 20;
+
+
+// Avatars
+pub const AVATAR_MAX_BYTES: usize = 5 * 1024 * 1024;
+pub const AVATAR_MAX_DIMENSION: u32 = 4096;
+// Memory the decoder may allocate, guards against decompression bombs
+pub const AVATAR_MAX_ALLOC: u64 = 128 * 1024 * 1024;
+// Pixel sizes of the square images generated for every avatar
+pub const AVATAR_SIZES: [u32; 3] = [64, 256, 512];
+
+
+// Attachments
+pub const ATTACHMENT_MAX_SIZE: i64 = 100 * 1024 * 1024;
+pub const ATTACHMENT_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+pub const ATTACHMENT_FILE_NAME_MAX_LENGTH: usize = 255;
+// Uploads not completed within it are deleted together with their chunks
+pub const ATTACHMENT_UPLOAD_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+pub const ATTACHMENT_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// Completed attachments which have not been sent in any message within it are deleted
+pub const ATTACHMENT_UNSENT_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+// Limits on the uploads a user has started and not completed yet
+pub const ATTACHMENT_MAX_PENDING_UPLOADS: i64 = 10;
+pub const ATTACHMENT_MAX_PENDING_BYTES: i64 = 4 * ATTACHMENT_MAX_SIZE;
+pub const MESSAGE_MAX_ATTACHMENTS: usize = 10;
+
+
+// Devices
+pub const DEVICE_MAX_PER_USER: usize = 20;
+pub const DEVICE_NAME_MAX_LENGTH: usize = 64;
+pub const DEVICE_PLATFORM_MAX_LENGTH: usize = 32;
+pub const DEVICE_SIGNATURE_KEY_MAX_LENGTH: usize = 256;
+
+
+// Chat
+pub const CHAT_MAX_MEMBERS: usize = 1000;
+pub const CHAT_MAX_PINS: i64 = 50;
+pub const MESSAGE_HISTORY_PAGE_SIZE: i64 = 100;
+// Characters of the replied message kept in the quote
+pub const MESSAGE_QUOTE_MAX_LENGTH: usize = 500;
+pub const MESSAGE_MAX_DISTINCT_REACTIONS: i64 = 20;
+// In characters, an emoji may consist of several code points
+pub const REACTION_MAX_LENGTH: usize = 16;
+pub const MESSAGE_MAX_FORWARD: usize = 100;
+pub const MESSAGE_MAX_MENTIONS: usize = 50;
+pub const MESSAGE_MAX_ENTITIES: usize = 100;
+pub const ENTITY_URL_MAX_LENGTH: usize = 2048;
+pub const ENTITY_LANGUAGE_MAX_LENGTH: usize = 32;
+pub const MESSAGE_SEARCH_MAX_QUERY_LENGTH: usize = 256;
+pub const MESSAGE_SEARCH_PAGE_SIZE: i64 = 50;
+
+
+// MLS
+pub const KEY_PACKAGE_MAX_BATCH: usize = 100;
+pub const KEY_PACKAGE_MAX_PER_DEVICE: usize = 500;
+// Claims drain the one-time stock of the target devices, so they are limited per claimer
+pub const KEY_PACKAGE_CLAIM_WINDOW: Duration = Duration::from_secs(60 * 60);
+pub const KEY_PACKAGE_MAX_CLAIMS_PER_WINDOW: i64 = 500;
+pub const KEY_PACKAGE_MAX_CLAIMS_PER_TARGET: i64 = 50;
+// Devices are asked to upload more KeyPackages when their one-time stock falls below it
+pub static KEY_PACKAGE_LOW_STOCK_THRESHOLD: LazyLock<i64> = LazyLock::new(|| env_or("KEY_PACKAGE_LOW_STOCK_THRESHOLD", 10));
+pub const MLS_HISTORY_PAGE_SIZE: i64 = 100;
+// Allowed ciphersuites, the first one is preferred. Default: MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519
+pub static MLS_CIPHERSUITES: LazyLock<Vec<u16>> = LazyLock::new(|| env_u16_list("MLS_CIPHERSUITES", &[0x0001]));
+// Allowed extension types. Default: application_id, ratchet_tree, required_capabilities,
+// external_pub, external_senders and last_resort
+pub static MLS_EXTENSIONS: LazyLock<Vec<u16>> = LazyLock::new(|| {
+    env_u16_list("MLS_EXTENSIONS", &[0x0001, 0x0002, 0x0003, 0x0004, 0x0005, 0x000a])
+});
+
 
 // Authorization
 pub const AUTHORIZATION_HEADER: &str = "Authorization";
@@ -0,0 +1,112 @@
+use std::{io::ErrorKind, path::PathBuf};
+
+use rand::{distr::Alphanumeric, Rng};
+use tokio::{fs, io::{self, AsyncRead, AsyncWriteExt}};
+
+use crate::{models::errors::ModelError, storage::storage::{BlobReader, BlobStore}};
+
+
+/// Keeps objects as files under the root directory, a key is the relative path of its file.
+/// Only one server instance can use it, unless the directory is shared.
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    pub async fn open(root: &str) -> Self {
+        fs::create_dir_all(root)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to create the storage directory `{}`: {}", root, e));
+        Self { root: PathBuf::from(root) }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, ModelError> {
+        let valid = !key.is_empty() && key.split('/').all(|segment| {
+            !segment.is_empty() && segment != "." && segment != ".."
+                && segment.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+        });
+        if !valid {
+            tracing::error!("Invalid storage key `{}`", key);
+            return Err(ModelError::UnexpectedError("Internal server error".to_string()))
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+impl BlobStore for LocalStore {
+    async fn put(&self, key: &str, mut data: impl AsyncRead + Send + Unpin) -> Result<(), ModelError> {
+        let path = self.path(key)?;
+        let suffix: String = rand::rng().sample_iter(&Alphanumeric).take(8).map(char::from).collect();
+        let temp_path = path.with_extension(format!("{}.tmp", suffix));
+
+        // Readers never see a partially written object
+        let result = async {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            let mut file = fs::File::create(&temp_path).await?;
+            io::copy(&mut data, &mut file).await?;
+            file.flush().await?;
+            fs::rename(&temp_path, &path).await
+        }.await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                let _ = fs::remove_file(&temp_path).await;
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<BlobReader>, ModelError> {
+        match fs::File::open(self.path(key)?).await {
+            Ok(_file) => Ok(Some(Box::pin(_file))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), ModelError> {
+        match fs::remove_file(self.path(key)?).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Err(ModelError::UnexpectedError("Internal server error".to_string()))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::LocalStore;
+
+    fn store() -> LocalStore {
+        LocalStore { root: PathBuf::from("/var/lib/chatree") }
+    }
+
+    #[test]
+    fn keeps_keys_under_the_root() {
+        let Ok(path) = store().path("attachments/42/chunks/0") else {
+            panic!("valid key rejected")
+        };
+        assert_eq!(path, PathBuf::from("/var/lib/chatree/attachments/42/chunks/0"));
+        assert!(store().path("avatars/ab12/64.png").is_ok());
+    }
+
+    #[test]
+    fn rejects_escaping_keys() {
+        for key in ["", "..", "../etc/passwd", "avatars/../../etc/passwd", "/etc/passwd", "avatars//64.png", "avatars/./64.png", "avatars/", "avatars\\..\\x", "avatars/64 .png"] {
+            assert!(store().path(key).is_err(), "{:?}", key);
+        }
+    }
+}
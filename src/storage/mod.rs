@@ -0,0 +1,2 @@
+pub mod storage;
+pub mod local;
@@ -0,0 +1,82 @@
+use std::{future::Future, pin::Pin, str::FromStr};
+
+use axum::body::Body;
+use tokio::io::AsyncRead;
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    models::errors::ModelError,
+    settings::{STORAGE_BACKEND, STORAGE_PATH},
+    storage::local::LocalStore
+};
+
+
+/// Content of a stored object, read as it is needed
+pub type BlobReader = Pin<Box<dyn AsyncRead + Send>>;
+
+/// Store of binary objects addressed by `/`-separated keys.
+/// Objects are streamed both ways, so their size is not limited by memory.
+pub trait BlobStore {
+    /// Writes the object until the reader ends, an existing object is replaced once the new one is complete
+    fn put(&self, key: &str, data: impl AsyncRead + Send + Unpin) -> impl Future<Output = Result<(), ModelError>> + Send;
+    fn get(&self, key: &str) -> impl Future<Output = Result<Option<BlobReader>, ModelError>> + Send;
+    /// Deleting a missing object is not an error
+    fn delete(&self, key: &str) -> impl Future<Output = Result<(), ModelError>> + Send;
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageBackendKind {
+    Local,
+}
+
+impl FromStr for StorageBackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "local" => Ok(Self::Local),
+            _ => Err(format!("Unknown storage backend `{}`", s))
+        }
+    }
+}
+
+
+/// Blob store selected by the `STORAGE_BACKEND` setting
+pub enum StorageBackend {
+    Local(LocalStore),
+}
+
+impl StorageBackend {
+    pub async fn from_settings() -> Self {
+        match *STORAGE_BACKEND {
+            StorageBackendKind::Local => Self::Local(LocalStore::open(STORAGE_PATH.as_str()).await),
+        }
+    }
+}
+
+impl BlobStore for StorageBackend {
+    async fn put(&self, key: &str, data: impl AsyncRead + Send + Unpin) -> Result<(), ModelError> {
+        match self {
+            Self::Local(store) => store.put(key, data).await,
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<BlobReader>, ModelError> {
+        match self {
+            Self::Local(store) => store.get(key).await,
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), ModelError> {
+        match self {
+            Self::Local(store) => store.delete(key).await,
+        }
+    }
+}
+
+
+/// Response body streaming the object
+pub fn stream_body(reader: BlobReader) -> Body {
+    Body::from_stream(ReaderStream::new(reader))
+}
@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use axum::{extract::{Path, State}, http::StatusCode, response::Response, Json};
+
+use crate::{
+    app_state::AppState,
+    auth::jwt_authorization::JWTAuthorize,
+    models::{block::Block, errors::ModelError, user::{User, UserID, UserProfile}}
+};
+
+
+pub async fn list_blocks(
+    JWTAuthorize(jwt): JWTAuthorize,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<UserProfile>>, Response> {
+    let blocked = Block::list(jwt.claims.user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    Ok(Json(blocked))
+}
+
+
+pub async fn block_user(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path(user_id): Path<UserID>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response> {
+    if user_id == jwt.claims.user_id {
+        let err = ModelError::ClientError("You can not block yourself".to_string());
+        return Err(ModelError::into_error_response(err, None, None))
+    }
+    let exists = User::find_profile(user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .is_some();
+    if !exists {
+        let err = ModelError::ClientError("User not found".to_string());
+        return Err(ModelError::into_error_response(err, Some(StatusCode::NOT_FOUND), None))
+    }
+
+    // Blocking twice is not an error
+    Block { blocker_id: jwt.claims.user_id, blocked_id: user_id }
+        .insert(&state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+pub async fn unblock_user(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Path(user_id): Path<UserID>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response> {
+    let deleted = Block { blocker_id: jwt.claims.user_id, blocked_id: user_id }
+        .delete(&state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    if !deleted {
+        let err = ModelError::ClientError("The user is not blocked".to_string());
+        return Err(ModelError::into_error_response(err, Some(StatusCode::NOT_FOUND), None))
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,3 @@
+pub mod blocks;
+pub mod profile;
+pub mod search;
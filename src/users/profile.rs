@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use axum::{extract::{Path, State}, http::StatusCode, response::Response, Json};
+use serde::Deserialize;
+
+use crate::{
+    app_state::AppState,
+    auth::jwt_authorization::JWTAuthorize,
+    bus::bus::{ClusterEvent, EventBus},
+    models::{errors::ModelError, user::{ProfileUpdate, User, UserID, UserProfile}},
+    settings::{PROFILE_BIO_MAX_LENGTH, PROFILE_NAME_MAX_LENGTH, PROFILE_STATUS_MAX_LENGTH}
+};
+
+
+/// Omitted fields are left unchanged, an empty `status` or `bio` clears it
+#[derive(Debug, Deserialize)]
+pub struct UpdateProfileForm {
+    pub name: Option<String>,
+    pub status: Option<String>,
+    pub bio: Option<String>,
+    /// Whether the user can be found by the search
+    pub discoverable: Option<bool>,
+    /// Whether forwarded messages of the user link back to the user and the chat
+    pub link_forwards: Option<bool>,
+}
+
+impl UpdateProfileForm {
+    fn validate(self) -> Result<ProfileUpdate, ModelError> {
+        let name = match self.name.as_deref().map(str::trim) {
+            Some(name) if name.is_empty() || name.chars().count() > PROFILE_NAME_MAX_LENGTH => {
+                return Err(ModelError::ClientError(format!("Name must contain from 1 to {} characters", PROFILE_NAME_MAX_LENGTH)))
+            }
+            name => name.map(str::to_string),
+        };
+        Ok(ProfileUpdate {
+            name,
+            status: optional_field("Status", self.status, PROFILE_STATUS_MAX_LENGTH)?,
+            bio: optional_field("Bio", self.bio, PROFILE_BIO_MAX_LENGTH)?,
+            discoverable: self.discoverable,
+            link_forwards: self.link_forwards,
+        })
+    }
+}
+
+fn optional_field(field: &str, value: Option<String>, max_length: usize) -> Result<Option<Option<String>>, ModelError> {
+    let Some(value) = value else {
+        return Ok(None)
+    };
+    let value = value.trim();
+    if value.chars().count() > max_length {
+        return Err(ModelError::ClientError(format!("{} can not be longer than {} characters", field, max_length)))
+    }
+    Ok(Some((!value.is_empty()).then(|| value.to_string())))
+}
+
+
+async fn find_profile(user_id: UserID, state: &Arc<AppState>) -> Result<Json<UserProfile>, Response> {
+    User::find_profile(user_id, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .map(Json)
+        .ok_or_else(|| {
+            let err = ModelError::ClientError("User not found".to_string());
+            ModelError::into_error_response(err, Some(StatusCode::NOT_FOUND), None)
+        })
+}
+
+
+pub async fn profile(
+    JWTAuthorize(_jwt): JWTAuthorize,
+    Path(user_id): Path<UserID>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<UserProfile>, Response> {
+    find_profile(user_id, &state).await
+}
+
+
+pub async fn me(
+    JWTAuthorize(jwt): JWTAuthorize,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<UserProfile>, Response> {
+    find_profile(jwt.claims.user_id, &state).await
+}
+
+
+pub async fn update_me(
+    JWTAuthorize(jwt): JWTAuthorize,
+    State(state): State<Arc<AppState>>,
+    Json(form): Json<UpdateProfileForm>,
+) -> Result<Json<UserProfile>, Response> {
+    let user_id = jwt.claims.user_id;
+    let update = form.validate()
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+
+    let profile = User::update_profile(user_id, &update, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?
+        .ok_or_else(|| {
+            let err = ModelError::ClientError("User not found".to_string());
+            ModelError::into_error_response(err, Some(StatusCode::NOT_FOUND), None)
+        })?;
+
+    state.bus.publish(ClusterEvent::ProfileUpdated { user_id }).await;
+    Ok(Json(profile))
+}
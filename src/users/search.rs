@@ -0,0 +1,48 @@
+use std::sync::Arc;
+
+use axum::{extract::{Query, State}, response::Response, Json};
+use serde::Deserialize;
+
+use crate::{
+    access::blocks,
+    app_state::AppState,
+    auth::jwt_authorization::JWTAuthorize,
+    models::{errors::ModelError, user::{User, UserProfile}},
+    settings::{USER_SEARCH_MAX_QUERY_LENGTH, USER_SEARCH_MIN_QUERY_LENGTH, USER_SEARCH_PAGE_SIZE}
+};
+
+
+#[derive(Debug, Deserialize)]
+pub struct UserSearchQuery {
+    pub q: String,
+    #[serde(default)]
+    pub offset: i64,
+    pub limit: Option<i64>,
+}
+
+
+pub async fn search_users(
+    JWTAuthorize(jwt): JWTAuthorize,
+    Query(query): Query<UserSearchQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<UserProfile>>, Response> {
+    let q = query.q.trim().trim_start_matches('@');
+    let length = q.chars().count();
+    if !(USER_SEARCH_MIN_QUERY_LENGTH..=USER_SEARCH_MAX_QUERY_LENGTH).contains(&length) {
+        let err = ModelError::ClientError(format!(
+            "Query must contain from {} to {} characters",
+            USER_SEARCH_MIN_QUERY_LENGTH,
+            USER_SEARCH_MAX_QUERY_LENGTH
+        ));
+        return Err(ModelError::into_error_response(err, None, None))
+    }
+
+    let limit = query.limit.unwrap_or(USER_SEARCH_PAGE_SIZE).clamp(1, USER_SEARCH_PAGE_SIZE);
+    let hidden_ids = blocks::hidden_user_ids(&state, jwt.claims.user_id)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    let users = User::search(q, jwt.claims.user_id, &hidden_ids, query.offset.max(0), limit, &state.pool)
+        .await
+        .map_err(|err| ModelError::into_error_response(err, None, None))?;
+    Ok(Json(users))
+}
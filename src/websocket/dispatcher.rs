@@ -0,0 +1,162 @@
+use std::sync::Arc;
+
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    app_state::AppState,
+    bus::bus::{ClusterEvent, EventBus},
+    models::{chat::{Chat, ChatID, ChatUser}, device::DeviceID, message::Message, mls::{MlsMessage, MlsMessageDTO, MlsWelcome, MlsWelcomeDTO}, user::{User, UserID}},
+    websocket::protocol::{MessageDelivery, WsEvent}
+};
+
+
+/// Forwards cluster events to the clients connected to this instance
+pub fn spawn_dispatcher(state: Arc<AppState>) {
+    let mut rx = state.bus.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => dispatch(&state, event).await,
+                Err(RecvError::Lagged(skipped)) => tracing::warn!("Dispatcher lagged behind, {} event(s) skipped", skipped),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+
+async fn dispatch(state: &Arc<AppState>, event: ClusterEvent) {
+    match event {
+        ClusterEvent::MessageCreated { chat_id, message_id } => {
+            match Message::find(message_id, &state.pool).await {
+                Ok(Some(message)) => {
+                    let mentions = message.mentions.clone();
+                    send_message(state, chat_id, message).await;
+                    for mentioned_id in mentions {
+                        send_to_user(state, mentioned_id, &WsEvent::Mention { chat_id, message_id }).await;
+                    }
+                }
+                Ok(None) => tracing::warn!("Message `{}` from cluster event not found", message_id),
+                Err(_) => {},
+            }
+        }
+        ClusterEvent::MlsMessageCreated { chat_id, message_id } => {
+            match MlsMessage::find(message_id, &state.pool).await {
+                Ok(Some(message)) => send_to_chat(state, chat_id, &WsEvent::MlsMessage(MlsMessageDTO::from(message))).await,
+                Ok(None) => tracing::warn!("MLS message `{}` from cluster event not found", message_id),
+                Err(_) => {},
+            }
+        }
+        ClusterEvent::KeyPackageStockLow { user_id, device_id, stock } => {
+            send_to_device(state, user_id, device_id, &WsEvent::KeyPackageStockLow { device_id, stock }).await;
+        }
+        ClusterEvent::DeviceRemoved { user_id, device_id } => {
+            // Dropping the channel closes the sockets of the device
+            let mut clients = state.clients.write().await;
+            if let Some(devices) = clients.get_mut(&user_id) {
+                devices.remove(&device_id);
+                if devices.is_empty() {
+                    clients.remove(&user_id);
+                }
+            }
+        }
+        ClusterEvent::WelcomeCreated { user_id, welcome_id } => {
+            // Already acknowledged Welcomes are not found, which is fine
+            if let Ok(Some(welcome)) = MlsWelcome::find(welcome_id, &state.pool).await {
+                send_to_device(state, user_id, welcome.device_id, &WsEvent::Welcome(MlsWelcomeDTO::from(welcome))).await;
+            }
+        }
+        ClusterEvent::ReactionChanged { chat_id, message_id, user_id, emoji, added } => {
+            send_to_chat(state, chat_id, &WsEvent::Reaction { chat_id, message_id, user_id, emoji, added }).await;
+        }
+        ClusterEvent::PinChanged { chat_id, message_id, user_id, pinned } => {
+            send_to_chat(state, chat_id, &WsEvent::Pin { chat_id, message_id, user_id, pinned }).await;
+        }
+        ClusterEvent::ChatUpdated { chat_id } => {
+            if let Ok(Some(chat)) = Chat::find(chat_id, &state.pool).await {
+                send_to_chat(state, chat_id, &WsEvent::ChatUpdated(chat)).await;
+            }
+        }
+        ClusterEvent::ProfileUpdated { user_id } => {
+            if let Ok(Some(profile)) = User::find_profile(user_id, &state.pool).await {
+                send_to_peers(state, user_id, &WsEvent::ProfileUpdated(profile)).await;
+            }
+        }
+    }
+}
+
+
+/// Sends the event to every member of the chat connected to this instance
+pub async fn send_to_chat(state: &Arc<AppState>, chat_id: ChatID, event: &WsEvent) {
+    let member_ids = match ChatUser::member_ids(chat_id, &state.pool).await {
+        Ok(_member_ids) => _member_ids,
+        Err(_) => return,
+    };
+
+    let text = event.to_text();
+    let clients = state.clients.read().await;
+    for member_id in member_ids {
+        for tx in clients.get(&member_id).into_iter().flat_map(|devices| devices.values()) {
+            let _ = tx.send(text.clone());
+        }
+    }
+}
+
+
+/// Sends the message to every member of the chat connected to this instance, with the member's `notify` flag
+async fn send_message(state: &Arc<AppState>, chat_id: ChatID, message: Message) {
+    let members = match ChatUser::member_mutes(chat_id, &state.pool).await {
+        Ok(_members) => _members,
+        Err(_) => return,
+    };
+
+    let sender_id = message.user_id;
+    let mentions = message.mentions.clone();
+    let message = Box::new(message);
+    let notified = WsEvent::Message(MessageDelivery { message: message.clone(), notify: true }).to_text();
+    let silent = WsEvent::Message(MessageDelivery { message, notify: false }).to_text();
+
+    let clients = state.clients.read().await;
+    for (member_id, muted) in members {
+        let notify = member_id != sender_id && (!muted || mentions.contains(&member_id));
+        let text = if notify { &notified } else { &silent };
+        for tx in clients.get(&member_id).into_iter().flat_map(|devices| devices.values()) {
+            let _ = tx.send(text.clone());
+        }
+    }
+}
+
+
+/// Sends the event to the user and everyone sharing a chat with them, connected to this instance
+pub async fn send_to_peers(state: &Arc<AppState>, user_id: UserID, event: &WsEvent) {
+    let peer_ids = match ChatUser::peer_ids(user_id, &state.pool).await {
+        Ok(_peer_ids) => _peer_ids,
+        Err(_) => return,
+    };
+
+    let text = event.to_text();
+    let clients = state.clients.read().await;
+    for peer_id in peer_ids.into_iter().chain(std::iter::once(user_id)) {
+        for tx in clients.get(&peer_id).into_iter().flat_map(|devices| devices.values()) {
+            let _ = tx.send(text.clone());
+        }
+    }
+}
+
+
+/// Sends the event to every socket of the user connected to this instance
+pub async fn send_to_user(state: &Arc<AppState>, user_id: UserID, event: &WsEvent) {
+    let text = event.to_text();
+    for tx in state.clients.read().await.get(&user_id).into_iter().flat_map(|devices| devices.values()) {
+        let _ = tx.send(text.clone());
+    }
+}
+
+
+/// Sends the event to the sockets of one device of the user connected to this instance
+pub async fn send_to_device(state: &Arc<AppState>, user_id: UserID, device_id: DeviceID, event: &WsEvent) {
+    let clients = state.clients.read().await;
+    if let Some(tx) = clients.get(&user_id).and_then(|devices| devices.get(&device_id)) {
+        let _ = tx.send(event.to_text());
+    }
+}
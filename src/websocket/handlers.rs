@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use crate::{
+    app_state::AppState,
+    chat::{messages, reactions},
+    core::Base64,
+    crypto::delivery_service,
+    models::{device::DeviceID, errors::ModelError, user::UserID},
+    websocket::protocol::{WsEvent, WsRequest}
+};
+
+
+/// Handles a request of the client.
+/// Returns an event which is sent back only to the socket which has sent the request.
+pub async fn handle_request(state: &Arc<AppState>, user_id: UserID, device_id: DeviceID, request: WsRequest) -> Option<WsEvent> {
+    let result = match request {
+        WsRequest::SendMessage { chat_id, message } => {
+            messages::send_message(state, user_id, chat_id, message).await
+                .map(|_| None)
+        }
+        WsRequest::ForwardMessages { from_chat_id, chat_id, message_ids } => {
+            messages::forward_messages(state, user_id, from_chat_id, chat_id, message_ids).await
+                .map(|_| None)
+        }
+        WsRequest::AddReaction { message_id, emoji } => {
+            reactions::add_reaction(state, user_id, message_id, emoji).await
+                .map(|_| None)
+        }
+        WsRequest::RemoveReaction { message_id, emoji } => {
+            reactions::remove_reaction(state, user_id, message_id, emoji).await
+                .map(|_| None)
+        }
+        WsRequest::SendEncryptedMessage { chat_id, payload: Base64(payload), sender_leaf } => {
+            messages::send_encrypted_message(state, user_id, chat_id, payload, sender_leaf).await
+                .map(|_| None)
+        }
+        WsRequest::CreateMlsGroup { chat_id, group_id: Base64(group_id), ciphersuite } => {
+            delivery_service::create_group(state, user_id, chat_id, group_id, ciphersuite).await
+                .map(|_| Some(WsEvent::MlsGroupCreated { chat_id }))
+        }
+        WsRequest::SendMlsMessage { message: Base64(data) } => {
+            delivery_service::relay(state, user_id, device_id, data).await
+                .map(|message| Some(WsEvent::MlsMessageAccepted { message_id: message.id, epoch: message.epoch }))
+        }
+        WsRequest::SendWelcome { chat_id, welcome: Base64(data) } => {
+            delivery_service::route_welcome(state, user_id, chat_id, data).await
+                .map(|recipients| Some(WsEvent::WelcomeRouted { chat_id, recipients }))
+        }
+        WsRequest::AckWelcome { welcome_id } => {
+            delivery_service::acknowledge_welcome(state, user_id, device_id, welcome_id).await
+                .map(|_| None)
+        }
+    };
+
+    match result {
+        Ok(event) => event,
+        Err(ModelError::ClientError(error)) | Err(ModelError::UnexpectedError(error)) => Some(WsEvent::Error { error }),
+    }
+}
+
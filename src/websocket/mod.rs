@@ -1,2 +1,5 @@
 mod websocket;
-pub use websocket::{websocket, websocket_handler};
\ No newline at end of file
+pub mod dispatcher;
+pub mod handlers;
+pub mod protocol;
+pub use websocket::websocket_handler;
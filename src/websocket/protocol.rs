@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chat::messages::MessageDraft,
+    core::Base64,
+    models::{chat::{Chat, ChatID}, device::DeviceID, message::{Message, MessageID}, mls::{MlsMessageDTO, MlsWelcomeDTO}, user::{UserID, UserProfile}}
+};
+
+
+/// Request sent by a client over the websocket
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method", content = "data", rename_all = "snake_case")]
+pub enum WsRequest {
+    SendMessage {
+        chat_id: ChatID,
+        #[serde(flatten)]
+        message: MessageDraft,
+    },
+    /// Copies messages of `from_chat_id` into `chat_id`
+    ForwardMessages { from_chat_id: ChatID, chat_id: ChatID, message_ids: Vec<MessageID> },
+    AddReaction { message_id: MessageID, emoji: String },
+    RemoveReaction { message_id: MessageID, emoji: String },
+    /// TLS-serialized `MlsMessageOut` with a `PrivateMessage` for an encrypted chat
+    SendEncryptedMessage { chat_id: ChatID, payload: Base64, sender_leaf: u32 },
+    CreateMlsGroup { chat_id: ChatID, group_id: Base64, ciphersuite: Option<u16> },
+    /// TLS-serialized `MlsMessageOut` with a Commit or a Proposal
+    SendMlsMessage { message: Base64 },
+    /// TLS-serialized `MlsMessageOut` with a Welcome for the members added to the chat's group
+    SendWelcome { chat_id: ChatID, welcome: Base64 },
+    AckWelcome { welcome_id: i64 },
+}
+
+/// Message as it is delivered to one member
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageDelivery {
+    #[serde(flatten)]
+    pub message: Box<Message>,
+    /// Whether the member should be notified: never for the sender, for a muted chat only when mentioned
+    pub notify: bool,
+}
+
+/// Event sent by the server over the websocket
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum WsEvent {
+    Message(MessageDelivery),
+    MlsMessage(MlsMessageDTO),
+    MlsGroupCreated { chat_id: ChatID },
+    /// The message has been stored, for a Commit it means the client can merge it
+    MlsMessageAccepted { message_id: i64, epoch: i64 },
+    Welcome(MlsWelcomeDTO),
+    WelcomeRouted { chat_id: ChatID, recipients: usize },
+    /// The device should upload more KeyPackages
+    KeyPackageStockLow { device_id: DeviceID, stock: i64 },
+    /// A chat peer or the user itself has changed the profile
+    ProfileUpdated(UserProfile),
+    /// An owner has changed the settings or the avatar of the chat
+    ChatUpdated(Chat),
+    /// A member has added (`added`) or removed a reaction
+    Reaction { chat_id: ChatID, message_id: MessageID, user_id: UserID, emoji: String, added: bool },
+    /// The user is mentioned in the message, sent even if the user has muted the chat
+    Mention { chat_id: ChatID, message_id: MessageID },
+    /// A member has pinned (`pinned`) or unpinned a message
+    Pin { chat_id: ChatID, message_id: MessageID, user_id: UserID, pinned: bool },
+    Error { error: String },
+}
+
+impl WsEvent {
+    pub fn to_text(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
@@ -1,91 +1,79 @@
 use std::sync::Arc;
 
-use axum::{extract::{ws::{Message, Utf8Bytes, WebSocket}, Query, State, WebSocketUpgrade}, http::StatusCode, response::IntoResponse};
+use axum::{extract::{ws::{Message, WebSocket}, Query, State, WebSocketUpgrade}, http::StatusCode, response::IntoResponse};
 use futures_util::{SinkExt, StreamExt};
+use tokio::sync::{broadcast::{self, error::RecvError}, mpsc};
 
-use crate::{app_state::AppState, auth::ticket::TicketQuery, models::user::UserID};
+use crate::{
+    app_state::AppState,
+    auth::ticket::{TicketBinding, TicketQuery},
+    models::{device::{Device, DeviceID}, user::UserID},
+    crypto::{auth_service, delivery_service},
+    settings::CLIENT_CHANNEL_CAPACITY,
+    websocket::{handlers::handle_request, protocol::{WsEvent, WsRequest}}
+};
 
 
 
 pub async fn websocket_handler(
     ws: WebSocketUpgrade,
     Query(ticket_query): Query<TicketQuery>,
+    binding: TicketBinding,
     State(state): State<Arc<AppState>>
 ) -> impl IntoResponse {
-    let user_id = match state.tickets.verify(&ticket_query.ticket).await{
-        Some(_user_id) => _user_id,
-        None => return (StatusCode::UNAUTHORIZED, "Invalid ticket").into_response()
+    let (user_id, device_id) = match state.tickets.consume(&ticket_query.ticket, &binding).await {
+        Ok(_owner) => _owner,
+        Err(err) => return (StatusCode::UNAUTHORIZED, err.to_string()).into_response()
     };
-    ws.on_upgrade(move |socket| websocket(socket, state, user_id))
+    ws.on_upgrade(move |socket| websocket(socket, state, user_id, device_id))
 }
 
 // This function deals with a single websocket connection, i.e., a single
 // connected client / user, for which we will spawn two independent tasks (for
 // receiving / sending chat messages).
-pub async fn websocket(stream: WebSocket, state: Arc<AppState>, user_id: UserID) {
+pub async fn websocket(stream: WebSocket, state: Arc<AppState>, user_id: UserID, device_id: DeviceID) {
     // By splitting, we can send and receive at the same time.
     let (mut sender, mut receiver) = stream.split();
 
-    // Loop until a text message is found.
-    // while let Some(Ok(message)) = receiver.next().await {
-    //     if let Message::Text(text) = message {
-    //         // If username that is sent by client is not taken, fill username string.
-    //         // check_username(&state, &mut username, name.as_str());
-
-    //         // If not empty we want to quit the loop else we want to quit function.
-    //         if !text.is_empty() {
-    //             break;
-    //         } else {
-    //             // Only send our client that username is taken.
-    //             let _ = sender
-    //                 .send(Message::Text(Utf8Bytes::from_static(
-    //                     "Username already taken.",
-    //                 )))
-    //                 .await;
-
-    //             return;
-    //         }
-    //     }
-    // }
-
-
-
-    while let Some(Ok(msg)) = receiver.next().await {
-        if let Message::Text(text) = msg {
-            let parsed: Result<WsRequest, _> = serde_json::from_str(&text);
-
-            match parsed {
-                Ok(request) => {
-                    if let Some(handler) = state.handlers.get(&request.method) {
-                        let response = handler(state.clone(), request.data).await;
-                        let response_str = serde_json::to_string(&response).unwrap_or_default();
-                        let _ = sender.send(Message::Binary(response_str.as_bytes())).await;
-                    } else {
-                        let _ = sender.send(Message::Binary("Unknown method".as_bytes())).await;
-                    }
-                }
-                Err(_) => {
-                    let _ = sender.send(Message::Text("Invalid JSON".to_string())).await;
-                }
-            }
-        }
+    // Every socket of the device subscribes to the same channel, the dispatcher sends events there.
+    // Only the registry holds the sender, the sockets are closed when the device is removed from it.
+    let mut rx = state.clients.write().await
+        .entry(user_id)
+        .or_default()
+        .entry(device_id)
+        .or_insert_with(|| broadcast::channel(CLIENT_CHANNEL_CAPACITY).0)
+        .subscribe();
+    // Responses to requests go only to the socket which has sent them.
+    let (reply_tx, mut reply_rx) = mpsc::unbounded_channel::<String>();
+    tracing::debug!("User `{}` connected from device `{}`", user_id, device_id);
+    let _ = Device::touch(device_id, &state.pool).await;
+
+    // Deliver the Welcomes which arrived while the device was offline, they stay until acknowledged.
+    for welcome in delivery_service::pending_welcomes(&state, user_id, device_id).await {
+        let _ = reply_tx.send(WsEvent::Welcome(welcome).to_text());
+    }
+    if let Some(stock) = auth_service::low_stock(&state, user_id, device_id).await {
+        let _ = reply_tx.send(WsEvent::KeyPackageStockLow { device_id, stock }.to_text());
     }
 
-
-
-    // We subscribe *before* sending the "joined" message, so that we will also
-    // display it to our client.
-    let mut rx = state.tx.subscribe();
-
-    // Now send the "joined" message to all subscribers.
-    let msg = format!("{username} joined.");
-    tracing::debug!("{msg}");
-    let _ = state.tx.send(msg);
-
-    // Spawn the first task that will receive broadcast messages and send text
-    // messages over the websocket to our client.
+    // Spawn the first task that will receive events and send them
+    // over the websocket to our client.
     let mut send_task = tokio::spawn(async move {
-        while let Ok(msg) = rx.recv().await {
+        loop {
+            let msg = tokio::select! {
+                event = rx.recv() => match event {
+                    Ok(_event) => _event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!("User `{}` lagged behind, {} event(s) skipped", user_id, skipped);
+                        continue
+                    }
+                    Err(RecvError::Closed) => {
+                        let _ = sender.send(Message::Close(None)).await;
+                        break
+                    }
+                },
+                Some(reply) = reply_rx.recv() => reply,
+            };
             // In any websocket error, break loop.
             if sender.send(Message::text(msg)).await.is_err() {
                 break;
@@ -93,30 +81,48 @@ pub async fn websocket(stream: WebSocket, state: Arc<AppState>, user_id: UserID)
         }
     });
 
-    // Clone things we want to pass (move) to the receiving task.
-    let tx = state.tx.clone();
-    let name = username.clone();
-
-    // Spawn a task that takes messages from the websocket, prepends the user
-    // name, and sends them to all broadcast subscribers.
+    // Spawn a task that takes requests from the websocket and handles them.
+    let recv_state = state.clone();
     let mut recv_task = tokio::spawn(async move {
-        while let Some(Ok(Message::Text(text))) = receiver.next().await {
-            // Add username before message.
-            let _ = tx.send(format!("{name}: {text}"));
+        while let Some(Ok(msg)) = receiver.next().await {
+            let Message::Text(text) = msg else {
+                continue
+            };
+            let response = match serde_json::from_str::<WsRequest>(&text) {
+                Ok(request) => handle_request(&recv_state, user_id, device_id, request).await,
+                Err(e) => Some(WsEvent::Error { error: format!("Invalid request: {}", e) }),
+            };
+            if let Some(event) = response {
+                let _ = reply_tx.send(event.to_text());
+            }
         }
     });
 
     // If any one of the tasks run to completion, we abort the other.
-    tokio::select! {
-        _ = &mut send_task => recv_task.abort(),
-        _ = &mut recv_task => send_task.abort(),
+    let send_finished = tokio::select! {
+        _ = &mut send_task => true,
+        _ = &mut recv_task => false,
     };
+    // Wait for the aborted task, so its receiver is dropped before the check below.
+    if send_finished {
+        recv_task.abort();
+        let _ = recv_task.await;
+    } else {
+        send_task.abort();
+        let _ = send_task.await;
+    }
 
-    // Send "user left" message (similar to "joined" above).
-    let msg = format!("{username} left.");
-    tracing::debug!("{msg}");
-    let _ = state.tx.send(msg);
-
-    // Remove username from map so new clients can take it again.
-    // state.clients.write().await.remove(&username);
-}
\ No newline at end of file
+    // Remove the channel when the last socket of the device is gone.
+    let mut clients = state.clients.write().await;
+    if let Some(devices) = clients.get_mut(&user_id) {
+        if devices.get(&device_id).is_some_and(|tx| tx.receiver_count() == 0) {
+            devices.remove(&device_id);
+        }
+        if devices.is_empty() {
+            clients.remove(&user_id);
+        }
+    }
+    drop(clients);
+    let _ = Device::touch(device_id, &state.pool).await;
+    tracing::debug!("User `{}` disconnected from device `{}`", user_id, device_id);
+}